@@ -0,0 +1,115 @@
+# mii python hook
+# offers to load a module when a script dies on a missing import
+#
+# the init scripts put this directory on PYTHONPATH. any sitecustomize that
+# would normally be picked up is still executed afterwards.
+
+import os
+import subprocess
+import sys
+
+if os.environ.get("MII_NO_COLORS"):
+    _col_red = _col_green = _col_cyan = _col_white = _col_reset = ""
+else:
+    _col_red = "\033[0;31m"
+    _col_green = "\033[0;32m"
+    _col_cyan = "\033[0;36m"
+    _col_white = "\033[0;37;1m"
+    _col_reset = "\033[0;39m"
+
+_prev_excepthook = sys.excepthook
+
+
+def _mii_candidates(package):
+    try:
+        out = subprocess.run(
+            [os.environ["MII_BIN"], "py", package],
+            stdout=subprocess.PIPE,
+            stderr=subprocess.DEVNULL,
+            universal_newlines=True,
+        ).stdout
+    except (KeyError, OSError):
+        return []
+
+    return [line.split('"')[1] for line in out.splitlines() if "{" in line]
+
+
+def _mii_select(mods):
+    if len(mods) == 1:
+        return mods[0]
+
+    sys.stderr.write("[mii] %sselect a module to load:%s\n" % (_col_cyan, _col_reset))
+    for num, mod in enumerate(mods, 1):
+        sys.stderr.write("\t%d) %s%s%s\n" % (num, _col_white, mod, _col_reset))
+    sys.stderr.write(
+        "[mii] %senter a selection%s (1-%d, q to abort) [1]: "
+        % (_col_cyan, _col_reset, len(mods))
+    )
+
+    try:
+        inp = input().strip() or "1"
+        if 1 <= int(inp) <= len(mods):
+            return mods[int(inp) - 1]
+    except (EOFError, ValueError):
+        pass
+
+    sys.stderr.write("[mii] cancelling..\n")
+    return None
+
+
+def _mii_excepthook(etype, value, tb):
+    name = getattr(value, "name", None)
+
+    # only missing modules, not broken extensions or circular imports
+    if not issubclass(etype, ModuleNotFoundError) or not name:
+        return _prev_excepthook(etype, value, tb)
+
+    package = name.split(".")[0]
+    mods = _mii_candidates(package)
+
+    _prev_excepthook(etype, value, tb)
+
+    if not mods:
+        return
+
+    # interactive sessions can't be restarted, just point at the modules
+    if hasattr(sys, "ps1") or sys.flags.interactive or not sys.stdin.isatty():
+        sys.stderr.write(
+            "[mii] %spython package \"%s\" is provided by:%s %s\n"
+            % (_col_cyan, package, _col_reset, " ".join(mods))
+        )
+        return
+
+    mod = _mii_select(mods)
+
+    if mod is None:
+        return
+
+    sys.stderr.write("[mii] %sloading %s..%s\n" % (_col_green, mod, _col_reset))
+    sys.stderr.flush()
+
+    # rerun the same command line with the module loaded
+    argv = getattr(sys, "orig_argv", [sys.executable] + sys.argv)
+    os.execvp(
+        "bash",
+        ["bash", "-c", 'module load "$0" && exec "$@"', mod, sys.executable] + argv[1:],
+    )
+
+
+def _mii_chain():
+    here = os.path.dirname(os.path.abspath(__file__))
+    path = [p for p in sys.path if os.path.abspath(p or ".") != here]
+    spec = importlib.machinery.PathFinder.find_spec("sitecustomize", path)
+
+    if spec is not None and spec.loader is not None:
+        module = importlib.util.module_from_spec(spec)
+        spec.loader.exec_module(module)
+
+
+# ModuleNotFoundError is new in python 3.6, older interpreters are left alone
+if sys.version_info >= (3, 6):
+    import importlib.machinery
+    import importlib.util
+
+    sys.excepthook = _mii_excepthook
+    _mii_chain()
@@ -5,75 +5,1140 @@
  */
 
 use crate::crawl;
+use crate::db;
+use crate::elf;
+use crate::lmod;
+use crate::tcl;
 
-use regex::{Regex, RegexBuilder};
+use std::collections::{HashMap, HashSet};
 use std::fs;
 use std::io;
-
-const LMOD_PATH_REG_SRC: &'static str =
-    r#"^\s*prepend_path\s*\(\s*"PATH"\s*,\s*"([^"]+)"\s*(?:,\s*":"\s*)?\)\s*$"#;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+use walkdir::WalkDir;
 
 pub struct Info {
     pub file: crawl::ModuleFile,
-    pub bins: Vec<String>,
+    pub facts: Vec<(&'static str, Vec<Fact>)>,
+    pub dirs: Vec<(String, Option<i64>)>, /* PATH directories with their mtimes */
+    pub seeded: bool,                     /* effects came from a spider cache */
+}
+
+/* a fact is one row of an analyzer's table, one string per field */
+pub type Fact = Vec<String>;
+
+/* Module is what analyzers get to look at. builtins only need the effects. */
+pub struct Module<'a> {
+    pub file: &'a crawl::ModuleFile,
+    pub effects: &'a Effects,
+}
+
+/*
+ * Analyzer extracts one type of fact from modulefiles. each analyzer gets a
+ * table of its own in the index, named after it, with a TEXT column for
+ * every field, so names and fields must be lowercase sql identifiers.
+ * site-specific analyzers are added to the Registry.
+ */
+
+pub trait Analyzer: Send + Sync {
+    fn name(&self) -> &'static str;
+    fn fields(&self) -> &'static [&'static str];
+    fn analyze(&self, module: &Module) -> Vec<Fact>;
+}
+
+/* Builtin is an analyzer backed by a plain function */
+#[derive(Clone, Copy)]
+pub struct Builtin {
+    name: &'static str,
+    fields: &'static [&'static str],
+    run: fn(&Module) -> Vec<Fact>,
+}
+
+impl Analyzer for Builtin {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn fields(&self) -> &'static [&'static str] {
+        self.fields
+    }
+
+    fn analyze(&self, module: &Module) -> Vec<Fact> {
+        (self.run)(module)
+    }
+}
+
+const BUILTINS: &[Builtin] = &[
+    Builtin {
+        name: "bins",
+        fields: &["name", "kind", "target", "needs"],
+        run: bin_facts,
+    },
+    Builtin {
+        name: "mans",
+        fields: &["page"],
+        run: |m| single(analyze_mans(m.effects)),
+    },
+    Builtin {
+        name: "libs",
+        fields: &["lib"],
+        run: |m| single(analyze_libs(m.effects)),
+    },
+    Builtin {
+        name: "pys",
+        fields: &["package"],
+        run: |m| single(analyze_pys(m.effects)),
+    },
+    Builtin {
+        name: "pkgconfigs",
+        fields: &["name", "version"],
+        run: |m| {
+            analyze_pcs(m.effects)
+                .into_iter()
+                .map(|x| vec![x.name, x.version])
+                .collect()
+        },
+    },
+    Builtin {
+        name: "cmakes",
+        fields: &["package"],
+        run: |m| single(analyze_cmakes(m.effects)),
+    },
+    Builtin {
+        name: "headers",
+        fields: &["header"],
+        run: |m| single(analyze_headers(m.effects)),
+    },
+    Builtin {
+        name: "deps",
+        fields: &["name"],
+        run: |m| single(m.effects.deps.clone()),
+    },
+    Builtin {
+        name: "conflicts",
+        fields: &["name"],
+        run: |m| single(m.effects.conflicts.clone()),
+    },
+    Builtin {
+        name: "families",
+        fields: &["name"],
+        run: |m| single(m.effects.families.clone()),
+    },
+    Builtin {
+        name: "envs",
+        fields: &["op", "var", "value"],
+        run: |m| {
+            m.effects
+                .env
+                .iter()
+                .map(|x| {
+                    let (op, var, value) = x.parts();
+                    vec![op.to_string(), var.to_string(), value.to_string()]
+                })
+                .collect()
+        },
+    },
+    Builtin {
+        name: "modulepaths",
+        fields: &["dir"],
+        run: |m| single(m.effects.paths("MODULEPATH")),
+    },
+    Builtin {
+        name: "meta",
+        fields: &["description", "category", "homepage", "keywords", "help"],
+        run: |m| {
+            let meta = analyze_meta(m.effects);
+            vec![vec![
+                meta.description,
+                meta.category,
+                meta.homepage,
+                meta.keywords,
+                meta.help,
+            ]]
+        },
+    },
+];
+
+/*
+ * Registry holds the analyzers an index is built with. every analyzer's table
+ * is created when the engine opens the index, so site analyzers have to be
+ * registered before then.
+ */
+
+pub struct Registry {
+    analyzers: Vec<Box<dyn Analyzer>>,
 }
 
-pub fn analyze(file: crawl::ModuleFile) -> Result<Info, io::Error> {
+impl Registry {
+    /* builtin() starts a registry with the analyzers every index has */
+    pub fn builtin() -> Registry {
+        let mut registry = Registry {
+            analyzers: Vec::new(),
+        };
+
+        for b in BUILTINS {
+            registry
+                .register(Box::new(*b))
+                .expect("invalid builtin analyzer");
+        }
+
+        registry
+    }
+
+    /*
+     * register() adds an analyzer. its name and fields become table and column
+     * names, so they have to be plain identifiers, and unique.
+     */
+
+    pub fn register(&mut self, analyzer: Box<dyn Analyzer>) -> Result<(), String> {
+        let name = analyzer.name();
+        let fields = analyzer.fields();
+
+        if !identifier(name) || db::CORE_TABLES.contains(&name) {
+            return Err(format!("invalid analyzer name \"{}\"", name));
+        }
+
+        if self.analyzers.iter().any(|x| x.name() == name) {
+            return Err(format!("analyzer \"{}\" is already registered", name));
+        }
+
+        if fields.is_empty() {
+            return Err(format!("analyzer \"{}\" has no fields", name));
+        }
+
+        for (i, field) in fields.iter().enumerate() {
+            if !identifier(field) || *field == "module" || fields[..i].contains(field) {
+                return Err(format!(
+                    "invalid field \"{}\" in analyzer \"{}\"",
+                    field, name
+                ));
+            }
+        }
+
+        self.analyzers.push(analyzer);
+        Ok(())
+    }
+
+    pub fn into_analyzers(self) -> Vec<Box<dyn Analyzer>> {
+        self.analyzers
+    }
+}
+
+/* identifier() accepts lowercase sql identifiers, which never need quoting */
+fn identifier(s: &str) -> bool {
+    s.starts_with(|c: char| c.is_ascii_lowercase() || c == '_')
+        && s.chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+}
+
+fn single(items: Vec<String>) -> Vec<Fact> {
+    items.into_iter().map(|x| vec![x]).collect()
+}
+
+fn bin_facts(m: &Module) -> Vec<Fact> {
+    analyze_bins(m.effects)
+        .into_iter()
+        .map(|x| {
+            vec![
+                x.name,
+                x.kind.as_str().to_string(),
+                x.target,
+                x.needs.join(" "),
+            ]
+        })
+        .collect()
+}
+
+/*
+ * Metadata is the descriptive information from whatis and help
+ */
+
+#[derive(Default)]
+pub struct Metadata {
+    pub description: String,
+    pub category: String,
+    pub homepage: String,
+    pub keywords: String,
+    pub help: String,
+}
+
+/*
+ * Bin is a command found in a PATH directory. target is the canonical path of
+ * the file which actually runs, so symlinked commands can be told apart from
+ * the real thing.
+ */
+
+pub struct Bin {
+    pub name: String,
+    pub kind: BinKind,
+    pub target: String,
+    pub needs: Vec<String>, /* sonames the module itself doesn't provide */
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BinKind {
+    Regular,
+    Symlink,
+    Broken,
+    Directory,
+}
+
+impl BinKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BinKind::Regular => "regular",
+            BinKind::Symlink => "symlink",
+            BinKind::Broken => "broken",
+            BinKind::Directory => "directory",
+        }
+    }
+}
+
+/*
+ * PkgConfig is a pkg-config package provided by a module
+ */
+
+pub struct PkgConfig {
+    pub name: String,
+    pub version: String,
+}
+
+/* how deep header trees are walked below an include dir */
+const MAX_HEADER_DEPTH: usize = 8;
+
+/* where the dynamic linker looks when nothing else says otherwise */
+const SYSTEM_LIB_DIRS: &[&str] = &[
+    "/lib",
+    "/lib64",
+    "/usr/lib",
+    "/usr/lib64",
+    "/lib/x86_64-linux-gnu",
+    "/usr/lib/x86_64-linux-gnu",
+    "/lib/aarch64-linux-gnu",
+    "/usr/lib/aarch64-linux-gnu",
+];
+
+/* compression suffixes man and info pages are commonly installed with */
+const PAGE_COMPRESSION_EXTS: &[&str] = &[".gz", ".bz2", ".xz", ".Z", ".lz", ".zst"];
+
+/*
+ * EnvOp describes a single environment modification made by a modulefile
+ */
+
+#[derive(Clone, Debug)]
+pub enum EnvOp {
+    Set(String, String),
+    Unset(String),
+    Prepend(String, String),
+    Append(String, String),
+}
+
+impl EnvOp {
+    /* parts() splits an operation into its kind, variable and value */
+    pub fn parts(&self) -> (&str, &str, &str) {
+        match self {
+            EnvOp::Set(var, value) => ("set", var, value),
+            EnvOp::Unset(var) => ("unset", var, ""),
+            EnvOp::Prepend(var, value) => ("prepend", var, value),
+            EnvOp::Append(var, value) => ("append", var, value),
+        }
+    }
+}
+
+/*
+ * Effects collects everything a modulefile does when it is loaded
+ */
+
+#[derive(Default)]
+pub struct Effects {
+    pub env: Vec<EnvOp>,
+    pub deps: Vec<String>,
+    pub conflicts: Vec<String>,
+    pub families: Vec<String>,
+    pub whatis: Vec<String>,
+    pub help: String,
+
+    /* modulerc directives: (module, symbolic version), (alias, target), names */
+    pub versions: Vec<(String, String)>,
+    pub aliases: Vec<(String, String)>,
+    pub hidden: Vec<String>,
+}
+
+impl Effects {
+    /* paths() returns every directory a modulefile adds to a path variable */
+    pub fn paths(&self, var: &str) -> Vec<String> {
+        let mut out: Vec<String> = Vec::new();
+
+        for op in &self.env {
+            match op {
+                EnvOp::Set(v, value) | EnvOp::Prepend(v, value) | EnvOp::Append(v, value)
+                    if v == var =>
+                {
+                    out.extend(
+                        value
+                            .split(':')
+                            .filter(|x| !x.is_empty())
+                            .map(|x| x.to_string()),
+                    );
+                }
+                EnvOp::Unset(v) if v == var => out.clear(),
+                _ => (),
+            }
+        }
+
+        out
+    }
+
+    /* prefixes() guesses install prefixes from PATH entries: /opt/foo/bin -> /opt/foo */
+    pub fn prefixes(&self) -> Vec<String> {
+        let mut out: Vec<String> = self
+            .paths("PATH")
+            .iter()
+            .map(|p| Path::new(p.trim_end_matches('/')))
+            .filter(|p| {
+                p.file_name()
+                    .map(|x| x == "bin" || x == "sbin")
+                    .unwrap_or(false)
+            })
+            .filter_map(|p| p.parent())
+            .map(|p| p.to_string_lossy().to_string())
+            .collect();
+
+        out.sort();
+        out.dedup();
+        out
+    }
+}
+
+/*
+ * analyze() evaluates a modulefile and runs every analyzer over the result
+ */
+
+pub fn analyze(
+    file: crawl::ModuleFile,
+    analyzers: &[Box<dyn Analyzer>],
+) -> Result<Info, io::Error> {
     let contents = fs::read_to_string(&file.path)?;
-    let bins = analyze_bins(&contents, &file.modtype);
 
-    Ok(Info {
-        file: file,
-        bins: bins,
-    })
+    let effects = match file.modtype {
+        crawl::ModuleType::LMOD => lmod::eval(&contents, &file),
+        crawl::ModuleType::TCL => tcl::eval(&contents, &file),
+        /* providers are analyzed from the effects their source reports */
+        crawl::ModuleType::PROVIDER(_) => Effects::default(),
+    };
+
+    Ok(run_analyzers(file, &effects, analyzers))
+}
+
+/*
+ * analyze_effects() runs the analyzers over effects known from elsewhere,
+ * like a spider cache or a source, without evaluating the modulefile
+ */
+
+pub fn analyze_effects(
+    file: crawl::ModuleFile,
+    effects: Effects,
+    analyzers: &[Box<dyn Analyzer>],
+) -> Result<Info, io::Error> {
+    Ok(run_analyzers(file, &effects, analyzers))
 }
 
-fn analyze_bins(contents: &String, modtype: &crawl::ModuleType) -> Vec<String> {
-    let paths = match modtype {
-        crawl::ModuleType::LMOD => extract_lmod_paths(contents),
-        _ => Vec::new(),
+fn run_analyzers(
+    file: crawl::ModuleFile,
+    effects: &Effects,
+    analyzers: &[Box<dyn Analyzer>],
+) -> Info {
+    let module = Module {
+        file: &file,
+        effects,
     };
 
-    paths
+    let facts = analyzers
+        .iter()
+        .map(|a| (a.name(), a.analyze(&module)))
+        .collect();
+
+    /* new commands in these don't touch the modulefile, so they're watched too */
+    let dirs = effects
+        .paths("PATH")
         .into_iter()
-        .map(|p| search_path(p))
-        .flatten()
-        .collect()
+        .map(|d| {
+            let mtime = dir_mtime(&d);
+            (d, mtime)
+        })
+        .collect();
+
+    Info {
+        file,
+        facts,
+        dirs,
+        seeded: false,
+    }
 }
 
-fn extract_lmod_paths(contents: &String) -> Vec<String> {
-    lazy_static! {
-        static ref LMOD_PATH_REG: Regex = RegexBuilder::new(LMOD_PATH_REG_SRC)
-            .multi_line(true)
-            .build()
-            .unwrap();
+/*
+ * analyze_meta() sorts whatis lines into metadata fields. lines usually look
+ * like "Key: value"; anything without a known key is part of the description.
+ */
+
+fn analyze_meta(effects: &Effects) -> Metadata {
+    let mut meta = Metadata {
+        help: effects.help.trim().to_string(),
+        ..Metadata::default()
+    };
+
+    for line in &effects.whatis {
+        let (key, value) = match line.find(':') {
+            Some(i) => (line[..i].trim().to_lowercase(), line[i + 1..].trim()),
+            None => (String::new(), line.trim()),
+        };
+
+        let field = match key.as_str() {
+            "description" | "short description" => &mut meta.description,
+            "category" => &mut meta.category,
+            "url" | "homepage" => &mut meta.homepage,
+            "keyword" | "keywords" => &mut meta.keywords,
+            "name" | "version" => continue,
+            _ => {
+                /* not a key after all, e.g. "Tools for x: y and z" */
+                let value = line.trim();
+
+                if !meta.description.is_empty() {
+                    meta.description.push(' ');
+                }
+
+                meta.description.push_str(value);
+                continue;
+            }
+        };
+
+        if !field.is_empty() {
+            field.push(' ');
+        }
+
+        field.push_str(value);
     }
 
-    LMOD_PATH_REG
-        .captures_iter(contents)
-        .map(|x| x[1].to_string())
-        .collect()
+    meta
+}
+
+/*
+ * analyze_bins() collects the commands on a module's PATH entries. like the
+ * shell, the first directory providing a name wins. broken symlinks and
+ * directories are classified but never reported as commands.
+ */
+
+fn analyze_bins(effects: &Effects) -> Vec<Bin> {
+    let mut out: Vec<Bin> = Vec::new();
+    let mut seen: HashSet<String> = HashSet::new();
+
+    for bin in effects.paths("PATH").into_iter().flat_map(search_path) {
+        match bin.kind {
+            BinKind::Regular | BinKind::Symlink => {
+                if seen.insert(bin.name.clone()) {
+                    out.push(bin);
+                }
+            }
+            BinKind::Broken | BinKind::Directory => {
+                debug!("Ignoring {} {} in PATH", bin.kind.as_str(), bin.target);
+            }
+        }
+    }
+
+    analyze_needs(effects, &mut out);
+    out
+}
+
+/*
+ * analyze_needs() finds the shared libraries each command needs which can't
+ * be found in its runpath, the module's own library dirs or the system dirs.
+ * those have to come from some other module.
+ */
+
+fn analyze_needs(effects: &Effects, bins: &mut [Bin]) {
+    let mut dirs: Vec<String> = effects
+        .paths("LD_LIBRARY_PATH")
+        .into_iter()
+        .chain(effects.paths("LIBRARY_PATH"))
+        .collect();
+
+    for prefix in effects.prefixes() {
+        dirs.push(format!("{}/lib", prefix));
+        dirs.push(format!("{}/lib64", prefix));
+    }
+
+    dirs.extend(SYSTEM_LIB_DIRS.iter().map(|x| x.to_string()));
+
+    let mut found: HashMap<String, bool> = HashMap::new();
+
+    for bin in bins.iter_mut() {
+        let needs = match elf::needs(Path::new(&bin.target)) {
+            Some(needs) => needs,
+            None => continue,
+        };
+
+        for lib in needs.libs {
+            let in_runpath = needs
+                .runpaths
+                .iter()
+                .any(|dir| Path::new(dir).join(&lib).exists());
+
+            let in_dirs = *found
+                .entry(lib.clone())
+                .or_insert_with(|| dirs.iter().any(|dir| Path::new(dir).join(&lib).exists()));
+
+            if !in_runpath && !in_dirs {
+                bin.needs.push(lib);
+            }
+        }
+    }
+}
+
+/*
+ * analyze_mans() collects page names from MANPATH and INFOPATH entries
+ * info documents are treated as pages too, so both are searchable by name
+ */
+
+fn analyze_mans(effects: &Effects) -> Vec<String> {
+    let mut pages: Vec<String> = effects
+        .paths("MANPATH")
+        .into_iter()
+        .flat_map(search_manpath)
+        .chain(
+            effects
+                .paths("INFOPATH")
+                .into_iter()
+                .flat_map(search_infopath),
+        )
+        .collect();
+
+    pages.sort();
+    pages.dedup();
+    pages
+}
+
+fn search_manpath(path: String) -> Vec<String> {
+    let mut output: Vec<String> = Vec::new();
+
+    if let Ok(sections) = fs::read_dir(path) {
+        for section in sections.flatten() {
+            /* only look at manN sections, skip catN and localized trees */
+            if !section.file_name().to_string_lossy().starts_with("man") {
+                continue;
+            }
+
+            if let Ok(entries) = fs::read_dir(section.path()) {
+                for entry in entries.flatten() {
+                    let name = entry.file_name().to_string_lossy().to_string();
+                    let name = strip_compression(&name);
+
+                    /* drop the section suffix: ls.1 -> ls, Foo::Bar.3pm -> Foo::Bar */
+                    match name.rfind('.') {
+                        Some(i) if i > 0 => output.push(name[..i].to_string()),
+                        _ => (),
+                    }
+                }
+            }
+        }
+    }
+
+    output
+}
+
+fn search_infopath(path: String) -> Vec<String> {
+    let mut output: Vec<String> = Vec::new();
+
+    if let Ok(entries) = fs::read_dir(path) {
+        for entry in entries.flatten() {
+            let name = entry.file_name().to_string_lossy().to_string();
+
+            /* gcc.info, gcc.info-1.gz -> gcc */
+            if let Some(i) = strip_compression(&name).find(".info") {
+                if i > 0 {
+                    output.push(name[..i].to_string());
+                }
+            }
+        }
+    }
+
+    output
+}
+
+/*
+ * analyze_libs() collects shared library names from LD_LIBRARY_PATH and
+ * LIBRARY_PATH entries
+ */
+
+fn analyze_libs(effects: &Effects) -> Vec<String> {
+    let mut libs: Vec<String> = effects
+        .paths("LD_LIBRARY_PATH")
+        .into_iter()
+        .chain(effects.paths("LIBRARY_PATH"))
+        .flat_map(search_libpath)
+        .collect();
+
+    libs.sort();
+    libs.dedup();
+    libs
 }
 
-fn search_path(path: String) -> Vec<String> {
+fn search_libpath(path: String) -> Vec<String> {
     let mut output: Vec<String> = Vec::new();
 
     if let Ok(entries) = fs::read_dir(path) {
-        for entry in entries {
-            if let Ok(entry) = entry {
-                if is_executable::is_executable(entry.path()) {
-                    output.push(
-                        entry
-                            .path()
-                            .file_name()
-                            .unwrap()
-                            .to_string_lossy()
-                            .to_string(),
+        for entry in entries.flatten() {
+            let name = entry.file_name().to_string_lossy().to_string();
+
+            if soname_base(&name).is_some() {
+                output.push(name);
+            }
+        }
+    }
+
+    output
+}
+
+/*
+ * dir_mtime() returns the modification time of a directory in nanoseconds,
+ * which changes whenever a file is added to or removed from it
+ */
+
+pub fn dir_mtime(dir: &str) -> Option<i64> {
+    let mtime = fs::metadata(dir).and_then(|x| x.modified()).ok()?;
+
+    mtime
+        .duration_since(UNIX_EPOCH)
+        .ok()
+        .map(|x| x.as_nanos() as i64)
+}
+
+/*
+ * soname_base() strips the version from a shared library name:
+ * libhdf5.so.103.1.0 -> libhdf5.so. returns None for non-libraries.
+ */
+
+pub fn soname_base(name: &str) -> Option<&str> {
+    name.match_indices(".so")
+        .map(|(i, _)| i + 3)
+        .find(|&end| end == name.len() || name[end..].starts_with('.'))
+        .map(|end| &name[..end])
+}
+
+/*
+ * analyze_pys() collects importable python packages from PYTHONPATH entries,
+ * site-packages dirs set by the modulefile and site-packages under prefixes
+ */
+
+fn analyze_pys(effects: &Effects) -> Vec<String> {
+    let mut dirs = effects.paths("PYTHONPATH");
+
+    for op in &effects.env {
+        if let EnvOp::Set(_, value) | EnvOp::Prepend(_, value) | EnvOp::Append(_, value) = op {
+            dirs.extend(
+                value
+                    .split(':')
+                    .filter(|x| x.trim_end_matches('/').ends_with("site-packages"))
+                    .map(|x| x.to_string()),
+            );
+        }
+    }
+
+    for prefix in effects.prefixes() {
+        for lib in &["lib", "lib64"] {
+            if let Ok(entries) = fs::read_dir(Path::new(&prefix).join(lib)) {
+                for entry in entries.flatten() {
+                    if entry.file_name().to_string_lossy().starts_with("python") {
+                        dirs.push(
+                            entry
+                                .path()
+                                .join("site-packages")
+                                .to_string_lossy()
+                                .to_string(),
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    dirs.sort();
+    dirs.dedup();
+
+    let mut pys: Vec<String> = dirs.into_iter().flat_map(search_pypath).collect();
+
+    pys.sort();
+    pys.dedup();
+    pys
+}
+
+fn search_pypath(path: String) -> Vec<String> {
+    let mut output: Vec<String> = Vec::new();
+
+    if let Ok(entries) = fs::read_dir(path) {
+        for entry in entries.flatten() {
+            let name = entry.file_name().to_string_lossy().to_string();
+            let is_dir = entry.path().is_dir();
+
+            if name.ends_with(".dist-info") || name.ends_with(".egg-info") {
+                /* numpy-1.21.0.dist-info -> numpy, plus its import names */
+                output.push(name.split('-').next().unwrap().to_string());
+
+                if let Ok(top) = fs::read_to_string(entry.path().join("top_level.txt")) {
+                    output.extend(
+                        top.lines()
+                            .map(|x| x.trim().to_string())
+                            .filter(|x| is_identifier(x)),
                     );
                 }
+            } else if is_dir {
+                if is_identifier(&name) && name != "__pycache__" {
+                    output.push(name);
+                }
+            } else if name.ends_with(".py") || name.ends_with(".so") || name.ends_with(".pyc") {
+                /* plain and extension modules: foo.py, foo.cpython-39-x86_64-linux-gnu.so */
+                let stem = name.split('.').next().unwrap();
+
+                if is_identifier(stem) {
+                    output.push(stem.to_string());
+                }
             }
         }
     }
 
     output
 }
+
+/*
+ * analyze_pcs() collects .pc files from PKG_CONFIG_PATH entries and the
+ * usual pkgconfig dirs under prefixes
+ */
+
+fn analyze_pcs(effects: &Effects) -> Vec<PkgConfig> {
+    let mut dirs = effects.paths("PKG_CONFIG_PATH");
+
+    for prefix in effects.prefixes() {
+        for sub in &["lib/pkgconfig", "lib64/pkgconfig", "share/pkgconfig"] {
+            dirs.push(Path::new(&prefix).join(sub).to_string_lossy().to_string());
+        }
+    }
+
+    dirs.sort();
+    dirs.dedup();
+
+    let mut pcs: Vec<PkgConfig> = dirs.into_iter().flat_map(search_pcpath).collect();
+
+    pcs.sort_by(|a, b| a.name.cmp(&b.name));
+    pcs.dedup_by(|a, b| a.name == b.name);
+    pcs
+}
+
+fn search_pcpath(path: String) -> Vec<PkgConfig> {
+    let mut output: Vec<PkgConfig> = Vec::new();
+
+    if let Ok(entries) = fs::read_dir(path) {
+        for entry in entries.flatten() {
+            let name = entry.file_name().to_string_lossy().to_string();
+
+            if let Some(name) = name.strip_suffix(".pc") {
+                output.push(PkgConfig {
+                    name: name.to_string(),
+                    version: fs::read_to_string(entry.path())
+                        .map(|x| pc_version(&x))
+                        .unwrap_or_default(),
+                });
+            }
+        }
+    }
+
+    output
+}
+
+/* pc_version() reads the Version: field, expanding ${var} references */
+fn pc_version(contents: &str) -> String {
+    let mut vars: HashMap<&str, String> = HashMap::new();
+
+    for line in contents.lines() {
+        let line = line.trim();
+
+        if let Some(version) = line.strip_prefix("Version:") {
+            return pc_expand(version.trim(), &vars);
+        }
+
+        if let Some(i) = line.find('=') {
+            let key = line[..i].trim();
+
+            if is_identifier(key) {
+                let value = pc_expand(line[i + 1..].trim(), &vars);
+                vars.insert(key, value);
+            }
+        }
+    }
+
+    String::new()
+}
+
+fn pc_expand(value: &str, vars: &HashMap<&str, String>) -> String {
+    let mut out = String::new();
+    let mut rest = value;
+
+    while let Some(start) = rest.find("${") {
+        out.push_str(&rest[..start]);
+        rest = &rest[start + 2..];
+
+        match rest.find('}') {
+            Some(end) => {
+                out.push_str(vars.get(&rest[..end]).map(|x| x.as_str()).unwrap_or(""));
+                rest = &rest[end + 1..];
+            }
+            None => break,
+        }
+    }
+
+    out.push_str(rest);
+    out
+}
+
+/*
+ * analyze_cmakes() collects cmake package configs find_package() would see
+ * from CMAKE_PREFIX_PATH entries. like cmake itself, prefixes of PATH entries
+ * are searched as well.
+ */
+
+fn analyze_cmakes(effects: &Effects) -> Vec<String> {
+    let mut prefixes = effects.paths("CMAKE_PREFIX_PATH");
+    prefixes.extend(effects.prefixes());
+
+    prefixes.sort();
+    prefixes.dedup();
+
+    let mut cmakes: Vec<String> = prefixes
+        .iter()
+        .flat_map(|p| cmake_dirs(Path::new(p)))
+        .flat_map(search_cmakepath)
+        .collect();
+
+    cmakes.sort();
+    cmakes.dedup();
+    cmakes
+}
+
+/*
+ * cmake_dirs() lists the config dirs find_package() searches under a prefix:
+ * the prefix itself, its package subdirs, and package subdirs of lib, lib64,
+ * share and their cmake dirs, each optionally followed by cmake or CMake
+ */
+
+fn cmake_dirs(prefix: &Path) -> Vec<PathBuf> {
+    let mut dirs = vec![prefix.to_path_buf()];
+    let mut parents = vec![prefix.to_path_buf()];
+
+    for lib in &["lib", "lib64", "share"] {
+        parents.push(prefix.join(lib));
+        parents.push(prefix.join(lib).join("cmake"));
+    }
+
+    for parent in parents {
+        if let Ok(entries) = fs::read_dir(&parent) {
+            for entry in entries.flatten() {
+                let path = entry.path();
+
+                if path.is_dir() {
+                    dirs.push(path.join("cmake"));
+                    dirs.push(path.join("CMake"));
+                    dirs.push(path);
+                }
+            }
+        }
+    }
+
+    dirs
+}
+
+fn search_cmakepath(path: PathBuf) -> Vec<String> {
+    let mut output: Vec<String> = Vec::new();
+
+    if let Ok(entries) = fs::read_dir(path) {
+        for entry in entries.flatten() {
+            let name = entry.file_name().to_string_lossy().to_string();
+
+            /* BoostConfig.cmake -> Boost, hdf5-config.cmake -> hdf5 */
+            let package = name
+                .strip_suffix("Config.cmake")
+                .or_else(|| name.strip_suffix("-config.cmake"));
+
+            if let Some(package) = package {
+                if !package.is_empty() {
+                    output.push(package.to_string());
+                }
+            }
+        }
+    }
+
+    output
+}
+
+/*
+ * analyze_headers() collects headers from compiler include path entries and
+ * prefix include dirs. headers are stored relative to their include dir, as
+ * they would be written in an #include: hdf5/H5Cpp.h
+ */
+
+fn analyze_headers(effects: &Effects) -> Vec<String> {
+    let mut dirs = effects.paths("CPATH");
+    dirs.extend(effects.paths("C_INCLUDE_PATH"));
+    dirs.extend(effects.paths("CPLUS_INCLUDE_PATH"));
+
+    for prefix in effects.prefixes() {
+        dirs.push(
+            Path::new(&prefix)
+                .join("include")
+                .to_string_lossy()
+                .to_string(),
+        );
+    }
+
+    dirs.sort();
+    dirs.dedup();
+
+    let mut headers: Vec<String> = dirs.into_iter().flat_map(search_includepath).collect();
+
+    headers.sort();
+    headers.dedup();
+    headers
+}
+
+fn search_includepath(path: String) -> Vec<String> {
+    let root = Path::new(&path);
+
+    WalkDir::new(root)
+        .follow_links(true)
+        .max_depth(MAX_HEADER_DEPTH)
+        .into_iter()
+        .flatten()
+        .filter(|e| e.file_type().is_file())
+        .filter_map(|e| {
+            e.path()
+                .strip_prefix(root)
+                .ok()
+                .map(|x| x.to_string_lossy().to_string())
+        })
+        .collect()
+}
+
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+
+    match chars.next() {
+        Some(c) if c.is_alphabetic() || c == '_' => chars.all(|c| c.is_alphanumeric() || c == '_'),
+        _ => false,
+    }
+}
+
+fn strip_compression(name: &str) -> &str {
+    for ext in PAGE_COMPRESSION_EXTS {
+        if let Some(stripped) = name.strip_suffix(ext) {
+            return stripped;
+        }
+    }
+
+    name
+}
+
+fn search_path(path: String) -> Vec<Bin> {
+    let mut output: Vec<Bin> = Vec::new();
+
+    if let Ok(entries) = fs::read_dir(path) {
+        for entry in entries.flatten() {
+            if let Some((kind, target)) = classify_bin(&entry.path()) {
+                output.push(Bin {
+                    name: entry.file_name().to_string_lossy().to_string(),
+                    kind,
+                    target: target.to_string_lossy().to_string(),
+                    needs: Vec::new(),
+                });
+            }
+        }
+    }
+
+    output
+}
+
+/*
+ * classify_bin() sorts out what a PATH entry really is, following symlinks.
+ * non-executable files aren't commands at all and give None.
+ */
+
+fn classify_bin(path: &Path) -> Option<(BinKind, PathBuf)> {
+    let meta = fs::symlink_metadata(path).ok()?;
+
+    if meta.file_type().is_symlink() {
+        return match fs::canonicalize(path) {
+            Err(_) => Some((BinKind::Broken, path.to_path_buf())),
+            Ok(target) if target.is_dir() => Some((BinKind::Directory, target)),
+            Ok(target) if is_executable::is_executable(&target) => Some((BinKind::Symlink, target)),
+            Ok(_) => None,
+        };
+    }
+
+    if meta.is_dir() {
+        return Some((BinKind::Directory, path.to_path_buf()));
+    }
+
+    if is_executable::is_executable(path) {
+        /* the bin dir itself may be behind a symlink */
+        let target = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+        return Some((BinKind::Regular, target));
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Named(&'static str, &'static [&'static str]);
+
+    impl Analyzer for Named {
+        fn name(&self) -> &'static str {
+            self.0
+        }
+
+        fn fields(&self) -> &'static [&'static str] {
+            self.1
+        }
+
+        fn analyze(&self, _: &Module) -> Vec<Fact> {
+            Vec::new()
+        }
+    }
+
+    #[test]
+    fn register() {
+        let mut registry = Registry::builtin();
+
+        assert!(registry
+            .register(Box::new(Named("licenses", &["tag"])))
+            .is_ok());
+        assert!(registry
+            .register(Box::new(Named("licenses", &["tag"])))
+            .is_err());
+        assert!(registry.register(Box::new(Named("bins", &["x"]))).is_err());
+        assert!(registry
+            .register(Box::new(Named("modules", &["x"])))
+            .is_err());
+        assert!(registry
+            .register(Box::new(Named("gpu-reqs", &["x"])))
+            .is_err());
+        assert!(registry.register(Box::new(Named("gpus", &[]))).is_err());
+        assert!(registry
+            .register(Box::new(Named("gpus", &["module"])))
+            .is_err());
+        assert!(registry
+            .register(Box::new(Named("gpus", &["a", "a"])))
+            .is_err());
+        assert!(registry
+            .register(Box::new(Named("gpus", &["x); DROP"])))
+            .is_err());
+    }
+}
@@ -8,6 +8,9 @@
  * second thread reads the module file contents and hashes them
  */
 
+use crate::lmod;
+use crate::modulerc;
+
 use fasthash::xx;
 use std::fs::File;
 use std::io::Read;
@@ -17,21 +20,60 @@ use std::thread;
 use walkdir::WalkDir;
 
 #[derive(Clone)]
+#[allow(clippy::upper_case_acronyms)]
 pub enum ModuleType {
     LMOD,
     TCL,
+    PROVIDER(&'static str), /* anything found by a source, see source.rs */
 }
 
+/*
+ * ModuleFile is an entry in the index: a provider of commands, libraries and
+ * the like. most are modulefiles, loaded with `module load <code>`. the others
+ * come from sources, and carry the command activating them instead.
+ */
+
 #[derive(Clone)]
 pub struct ModuleFile {
     pub path: PathBuf,
     pub code: String,
     pub modtype: ModuleType,
-    pub hash: Option<u32>, /* not filled at first. */
+    pub hash: Option<u32>,  /* not filled at first. */
+    pub chain: Vec<String>, /* modules to load first, for hierarchical modulepaths */
+    pub default: bool,      /* the rest is filled from .modulerc files */
+    pub hidden: bool,
+    pub aliases: Vec<String>,
+    pub activate: Option<String>, /* set for providers which aren't modulefiles */
 }
 
-pub fn crawl_sync(modulepath: String) -> Vec<ModuleFile> {
+impl ModuleFile {
+    /* kind() names the kind of provider: lmod, tcl, or the source's kind */
+    pub fn kind(&self) -> &'static str {
+        match self.modtype {
+            ModuleType::LMOD => "lmod",
+            ModuleType::TCL => "tcl",
+            ModuleType::PROVIDER(kind) => kind,
+        }
+    }
+}
+
+/* a file in a module tree which isn't a modulefile */
+pub struct Skipped {
+    pub path: PathBuf,
+    pub reason: String,
+}
+
+/* editor backups and patch leftovers, which can still carry a valid header */
+const BACKUP_SUFFIXES: &[&str] = &[
+    "~", ".orig", ".rej", ".bak", ".old", ".swp", ".tmp", ".save", ".patch", ".diff",
+];
+
+/* the header environment modules requires on tcl modulefiles */
+const TCL_MAGIC: &[u8] = b"#%Module";
+
+pub fn crawl_sync(modulepath: String) -> (Vec<ModuleFile>, Vec<Skipped>) {
     let mut output = Vec::new();
+    let mut skipped = Vec::new();
 
     let roots: Vec<String> = modulepath.split(':').map(|x| x.to_string()).collect();
 
@@ -46,7 +88,14 @@ pub fn crawl_sync(modulepath: String) -> Vec<ModuleFile> {
     let mut num_module_files = 0;
 
     while let Some(loc) = rx.recv().unwrap() {
-        num_module_files += 1;
+        if let Some(reason) = skip_name(&loc.path) {
+            debug!("Skipping {}: {}", loc.path.display(), reason);
+            skipped.push(Skipped {
+                path: loc.path,
+                reason: reason.to_string(),
+            });
+            continue;
+        }
 
         match File::open(&loc.path).and_then(|mut f| {
             let mut contents = Vec::new();
@@ -56,12 +105,31 @@ pub fn crawl_sync(modulepath: String) -> Vec<ModuleFile> {
         }) {
             Err(e) => warn!("Error reading module file {}: {}", loc.path.display(), e),
             Ok(data) => {
+                let modtype = match classify(&data) {
+                    Ok(modtype) => modtype,
+                    Err(reason) => {
+                        debug!("Skipping {}: {}", loc.path.display(), reason);
+                        skipped.push(Skipped {
+                            path: loc.path,
+                            reason: reason.to_string(),
+                        });
+                        continue;
+                    }
+                };
+
+                num_module_files += 1;
+
                 /* hash the data and add the module entry */
                 output.push(ModuleFile {
                     path: loc.path,
                     code: loc.code,
-                    modtype: loc.modtype,
+                    modtype,
                     hash: Some(xx::hash32(data)),
+                    chain: loc.chain,
+                    default: false,
+                    hidden: false,
+                    aliases: Vec::new(),
+                    activate: None,
                 });
             }
         }
@@ -69,6 +137,8 @@ pub fn crawl_sync(modulepath: String) -> Vec<ModuleFile> {
 
     walker.join().expect("failed to join FS walker");
 
+    modulerc::apply(&mut output);
+
     if num_module_files == 0 {
         warn!(
             "No module files found in MODULEPATH \"{}\". Check your configuration!",
@@ -76,12 +146,42 @@ pub fn crawl_sync(modulepath: String) -> Vec<ModuleFile> {
         );
     }
 
-    output
+    (output, skipped)
+}
+
+/* skip_name() rejects files which are obviously not modulefiles by name */
+fn skip_name(path: &Path) -> Option<&'static str> {
+    let name = path.file_name()?.to_string_lossy();
+
+    if name.starts_with('#') || BACKUP_SUFFIXES.iter().any(|x| name.ends_with(x)) {
+        return Some("backup or patch file");
+    }
+
+    None
+}
+
+/*
+ * classify() tells modulefiles apart by their contents, whatever they're
+ * named: tcl modulefiles start with the #%Module header, anything else has to
+ * be valid lua. gives the reason for skipping files which are neither.
+ */
+
+fn classify(data: &[u8]) -> Result<ModuleType, &'static str> {
+    if data.starts_with(TCL_MAGIC) {
+        return Ok(ModuleType::TCL);
+    }
+
+    match std::str::from_utf8(data) {
+        Ok(contents) if contents.trim().is_empty() => Err("empty file"),
+        Ok(contents) if lmod::is_lua(contents) => Ok(ModuleType::LMOD),
+        Ok(_) => Err("neither a #%Module header nor valid lua"),
+        Err(_) => Err("not a text file"),
+    }
 }
 
 fn crawl_gen(roots: Vec<String>, tx: Sender<Option<ModuleFile>>) {
     for root in roots {
-        crawl_dir(&Path::new(&root), &tx);
+        crawl_dir(Path::new(&root), &tx);
     }
 
     tx.send(None)
@@ -91,36 +191,37 @@ fn crawl_gen(roots: Vec<String>, tx: Sender<Option<ModuleFile>>) {
 fn crawl_dir(root: &Path, tx: &Sender<Option<ModuleFile>>) {
     let walker = WalkDir::new(root).into_iter();
 
-    for entry in walker.filter_entry(|e| {
-        e.file_name()
-            .to_str()
-            .map(|s| !s.starts_with("."))
-            .unwrap_or(false)
-    }) {
-        if let Ok(entry) = entry {
-            if entry.file_type().is_file() {
-                let path = entry.path();
-                let code_path = path.strip_prefix(root).unwrap();
-
-                let (mod_type, mod_code) = match path.extension() {
-                    Some(ext) => match ext.to_str() {
-                        Some("lua") => (
-                            ModuleType::LMOD,
-                            code_path.parent().unwrap().join(path.file_stem().unwrap()),
-                        ),
-                        _ => (ModuleType::TCL, code_path.to_path_buf()),
-                    },
-                    None => (ModuleType::TCL, code_path.to_path_buf()), /* noice */
-                };
-
-                tx.send(Some(ModuleFile {
-                    path: path.to_path_buf(),
-                    code: mod_code.to_string_lossy().to_string(),
-                    modtype: mod_type,
-                    hash: None,
-                }))
-                .expect("unexpected mpsc send fail");
-            }
+    for entry in walker
+        .filter_entry(|e| {
+            e.file_name()
+                .to_str()
+                .map(|s| !s.starts_with('.'))
+                .unwrap_or(false)
+        })
+        .flatten()
+    {
+        if entry.file_type().is_file() {
+            let path = entry.path();
+            let code_path = path.strip_prefix(root).unwrap();
+
+            /* lmod drops the .lua from module names, the type is found from the contents */
+            let mod_code = match path.extension().and_then(|x| x.to_str()) {
+                Some("lua") => code_path.parent().unwrap().join(path.file_stem().unwrap()),
+                _ => code_path.to_path_buf(),
+            };
+
+            tx.send(Some(ModuleFile {
+                path: path.to_path_buf(),
+                code: mod_code.to_string_lossy().to_string(),
+                modtype: ModuleType::TCL, /* until classify() reads it */
+                hash: None,
+                chain: Vec::new(),
+                default: false,
+                hidden: false,
+                aliases: Vec::new(),
+                activate: None,
+            }))
+            .expect("unexpected mpsc send fail");
         }
     }
 }
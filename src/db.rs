@@ -16,21 +16,195 @@ use std::path::Path;
 use crate::analysis;
 use crate::crawl;
 
+/*
+ * the index is only a cache, so whenever the layout changes the old tables are
+ * dropped and rebuilt from scratch on the next sync
+ */
+
+const SCHEMA_VERSION: i32 = 20;
+
+/* list columns are newline-separated, since module codes can't contain one */
+const LIST_SEP: &str = "\n";
+
+/* tables which don't hold analyzer facts */
+pub const CORE_TABLES: &[&str] = &["modules", "skipped", "dirs"];
+
 pub struct BinResult {
     pub code: String,
+    pub path: String, /* of the modulefile or provider */
     pub command: String,
+    pub chain: Vec<String>,
+    pub default: bool,
+    pub symlink: bool,
+    pub target: String,
+    pub needs: Vec<String>,
+    pub kind: String,
+    pub activate: String, /* set for providers which aren't modulefiles */
+}
+
+pub struct ManResult {
+    pub code: String,
+    pub page: String,
+}
+
+pub struct LibResult {
+    pub code: String,
+    pub lib: String,
+    pub chain: Vec<String>,
+}
+
+pub struct PyResult {
+    pub code: String,
+    pub package: String,
+}
+
+pub struct HeaderResult {
+    pub code: String,
+    pub header: String,
+}
+
+pub struct CMakeResult {
+    pub code: String,
+    pub package: String,
+}
+
+pub struct PkgConfigResult {
+    pub code: String,
+    pub name: String,
+    pub version: String,
+}
+
+pub struct EnvResult {
+    pub code: String,
+    pub op: String,
+    pub value: String,
+}
+
+pub struct ModuleInfo {
+    pub path: String,
+    pub code: String,
+    pub bins: Vec<String>,
+    pub description: String,
+    pub spec: String,
+    pub toolchain: String,
+    pub dependencies: Vec<String>,
+    pub category: String,
+    pub homepage: String,
+    pub keywords: String,
+    pub help: String,
+    pub chain: Vec<String>,
+    pub default: bool,
+    pub aliases: Vec<String>,
+    pub kind: String,
+    pub activate: String,
 }
 
 pub struct DB {
     conn: Connection,
 }
 
+/* a fact joined with the module it belongs to */
+struct FactRow {
+    module: String,
+    code: String,
+    chain: Vec<String>,
+    default: bool,
+    kind: String,
+    activate: String,
+    fields: Vec<String>,
+}
+
 impl DB {
-    pub fn initialize(db_path: &Path) {
+    /*
+     * initialize() creates the core tables and a table for every analyzer.
+     * fact tables look like: <analyzer> (module TEXT, <field> TEXT, ...)
+     * where module is the modulefile path. a table left over from an analyzer
+     * whose fields have changed since is rebuilt. names and fields are checked
+     * by analysis::Registry, so they're formatted in as they are.
+     */
+
+    pub fn initialize(db_path: &Path, analyzers: &[Box<dyn analysis::Analyzer>]) {
         match Connection::open(db_path) {
             Ok(conn) => {
+                let version: i32 = conn
+                    .query_row("PRAGMA user_version", NO_PARAMS, |row| row.get(0))
+                    .unwrap();
+
+                if version != SCHEMA_VERSION {
+                    for table in tables(&conn) {
+                        conn.execute(&format!("DROP TABLE IF EXISTS {}", table), NO_PARAMS)
+                            .unwrap();
+                    }
+
+                    conn.pragma_update(None, "user_version", &SCHEMA_VERSION)
+                        .unwrap();
+                }
+
                 /* initialize database tables */
-                conn.execute("CREATE TABLE IF NOT EXISTS modules (path TEXT UNIQUE, code TEXT, nonce INT, hash BIGINT, bins TEXT)", NO_PARAMS).unwrap();
+                conn.execute("CREATE TABLE IF NOT EXISTS modules (path TEXT UNIQUE, code TEXT, nonce INT, hash BIGINT, chain TEXT, isdefault INT, hidden INT, aliases TEXT, kind TEXT, activate TEXT, seeded INT)", NO_PARAMS).unwrap();
+                conn.execute(
+                    "CREATE TABLE IF NOT EXISTS skipped (path TEXT UNIQUE, reason TEXT, nonce INT)",
+                    NO_PARAMS,
+                )
+                .unwrap();
+
+                /* PATH directories of each module, checked for new commands on sync */
+                conn.execute(
+                    "CREATE TABLE IF NOT EXISTS dirs (module TEXT, dir TEXT, mtime BIGINT)",
+                    NO_PARAMS,
+                )
+                .unwrap();
+                conn.execute(
+                    "CREATE INDEX IF NOT EXISTS dirs_module ON dirs (module)",
+                    NO_PARAMS,
+                )
+                .unwrap();
+
+                let existing = tables(&conn);
+
+                for a in analyzers {
+                    let mut expected = vec!["module"];
+                    expected.extend(a.fields());
+
+                    if existing.iter().any(|x| x == a.name()) {
+                        if columns(&conn, a.name()) == expected {
+                            continue;
+                        }
+
+                        debug!("Fields of analyzer {} changed, rebuilding", a.name());
+                        conn.execute(&format!("DROP TABLE {}", a.name()), NO_PARAMS)
+                            .unwrap();
+                    }
+
+                    let columns: Vec<String> =
+                        a.fields().iter().map(|x| format!("{} TEXT", x)).collect();
+
+                    conn.execute(
+                        &format!(
+                            "CREATE TABLE {0} (module TEXT, {1})",
+                            a.name(),
+                            columns.join(", ")
+                        ),
+                        NO_PARAMS,
+                    )
+                    .unwrap();
+
+                    conn.execute(
+                        &format!("CREATE INDEX {0}_module ON {0} (module)", a.name()),
+                        NO_PARAMS,
+                    )
+                    .unwrap();
+
+                    conn.execute(
+                        &format!("CREATE INDEX {0}_{1} ON {0} ({1})", a.name(), a.fields()[0]),
+                        NO_PARAMS,
+                    )
+                    .unwrap();
+
+                    /* a new analyzer has to see every module, not just changed ones */
+                    conn.execute("UPDATE modules SET hash=NULL", NO_PARAMS)
+                        .unwrap();
+                }
             }
             Err(e) => {
                 panic!("Failed to open database file {}: {}", db_path.display(), e);
@@ -43,7 +217,7 @@ impl DB {
             Ok(conn) => {
                 conn.pragma_update(None, "journal_mode", &"WAL").unwrap();
 
-                DB { conn: conn }
+                DB { conn }
             }
             Err(e) => {
                 panic!("Failed to open database file: {}", e);
@@ -57,26 +231,59 @@ impl DB {
      */
 
     /*
-     * compare_modules checks if there is an up-to-date entry in the local db
+     * compare_modules checks if there is an up-to-date entry in the local db.
+     * an entry is also stale when one of its PATH directories changed, since
+     * commands are added to existing modules without touching the modulefile.
+     * entries seeded from a spider cache are only up to date while syncs are
+     * still seeded, otherwise they're evaluated in full.
      */
 
     pub fn compare_modules(
         &mut self,
         local: Vec<crawl::ModuleFile>,
         nonce: u32,
+        seeded: bool,
     ) -> Vec<crawl::ModuleFile> {
         let tx = self.conn.transaction().unwrap();
         let ret;
 
         {
+            let mut dirs = tx
+                .prepare("SELECT dir, mtime FROM dirs WHERE module=?")
+                .unwrap();
             let mut stmt = tx
-                .prepare("UPDATE modules SET nonce=?, code=? WHERE path=? AND hash=?")
+                .prepare("UPDATE modules SET nonce=?, code=?, chain=?, isdefault=?, hidden=?, aliases=? WHERE path=? AND hash=? AND (seeded=0 OR ?)")
                 .unwrap();
             ret = local
                 .into_iter()
                 .filter(|x| {
-                    stmt.execute(params![nonce, x.code, x.path.to_string_lossy(), x.hash])
+                    let changed = dirs
+                        .query_map(params![x.path.to_string_lossy()], |row| {
+                            Ok((row.get(0).unwrap(), row.get(1).unwrap()))
+                        })
                         .unwrap()
+                        .filter_map(Result::ok)
+                        .find(|(dir, mtime): &(String, Option<i64>)| {
+                            analysis::dir_mtime(dir) != *mtime
+                        });
+
+                    if let Some((dir, _)) = changed {
+                        debug!("{} changed, reanalyzing {}", dir, x.code);
+                        return true;
+                    }
+
+                    stmt.execute(params![
+                        nonce,
+                        x.code,
+                        x.chain.join(LIST_SEP),
+                        x.default,
+                        x.hidden,
+                        x.aliases.join(LIST_SEP),
+                        x.path.to_string_lossy(),
+                        x.hash,
+                        seeded
+                    ])
+                    .unwrap()
                         < 1
                 })
                 .collect();
@@ -87,24 +294,82 @@ impl DB {
     }
 
     /*
-     * update_modules synchronizes local analyzed modules to the db
+     * update_modules synchronizes local analyzed modules to the db.
+     * the facts of a module are replaced wholesale.
      */
 
-    pub fn update_modules(&mut self, res: &Vec<analysis::Info>, nonce: u32) {
+    pub fn update_modules(&mut self, res: &[analysis::Info], nonce: u32) {
         let tx = self.conn.transaction().unwrap();
 
         {
-            let mut stmt = tx.prepare("INSERT INTO modules VALUES (?1, ?2, ?3, ?4, ?5) ON CONFLICT(path) DO UPDATE SET code=?2, nonce=?3, hash=?4, bins=?5").unwrap();
+            let mut stmt = tx.prepare("INSERT INTO modules VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11) ON CONFLICT(path) DO UPDATE SET code=?2, nonce=?3, hash=?4, chain=?5, isdefault=?6, hidden=?7, aliases=?8, kind=?9, activate=?10, seeded=?11").unwrap();
 
             for m in res {
+                let path = m.file.path.to_string_lossy().to_string();
+
                 stmt.execute(params![
-                    m.file.path.to_string_lossy(),
+                    path,
                     m.file.code,
                     nonce,
                     m.file.hash,
-                    m.bins.join(":")
+                    m.file.chain.join(LIST_SEP),
+                    m.file.default,
+                    m.file.hidden,
+                    m.file.aliases.join(LIST_SEP),
+                    m.file.kind(),
+                    m.file.activate.clone().unwrap_or_default(),
+                    m.seeded
                 ])
                 .unwrap();
+
+                tx.prepare_cached("DELETE FROM dirs WHERE module=?")
+                    .unwrap()
+                    .execute(params![path])
+                    .unwrap();
+
+                for (dir, mtime) in &m.dirs {
+                    tx.prepare_cached("INSERT INTO dirs VALUES (?, ?, ?)")
+                        .unwrap()
+                        .execute(params![path, dir, mtime])
+                        .unwrap();
+                }
+
+                for (table, facts) in &m.facts {
+                    tx.prepare_cached(&format!("DELETE FROM {} WHERE module=?", table))
+                        .unwrap()
+                        .execute(params![path])
+                        .unwrap();
+
+                    for fact in facts {
+                        let slots = vec!["?"; fact.len() + 1].join(", ");
+
+                        tx.prepare_cached(&format!("INSERT INTO {} VALUES ({})", table, slots))
+                            .unwrap()
+                            .execute(std::iter::once(&path).chain(fact.iter()))
+                            .unwrap();
+                    }
+                }
+            }
+        }
+
+        tx.commit().expect("transaction failed");
+    }
+
+    /*
+     * update_skipped records the files in module trees which aren't modulefiles
+     */
+
+    pub fn update_skipped(&mut self, skipped: &[crawl::Skipped], nonce: u32) {
+        let tx = self.conn.transaction().unwrap();
+
+        {
+            let mut stmt = tx
+                .prepare("INSERT INTO skipped VALUES (?1, ?2, ?3) ON CONFLICT(path) DO UPDATE SET reason=?2, nonce=?3")
+                .unwrap();
+
+            for s in skipped {
+                stmt.execute(params![s.path.to_string_lossy(), s.reason, nonce])
+                    .unwrap();
             }
         }
 
@@ -135,6 +400,26 @@ impl DB {
             res = tx
                 .execute("DELETE FROM modules WHERE nonce!=$1", params![nonce])
                 .unwrap();
+
+            tx.execute("DELETE FROM skipped WHERE nonce!=$1", params![nonce])
+                .unwrap();
+
+            tx.execute(
+                "DELETE FROM dirs WHERE module NOT IN (SELECT path FROM modules)",
+                NO_PARAMS,
+            )
+            .unwrap();
+
+            for table in fact_tables(&tx) {
+                tx.execute(
+                    &format!(
+                        "DELETE FROM {} WHERE module NOT IN (SELECT path FROM modules)",
+                        table
+                    ),
+                    NO_PARAMS,
+                )
+                .unwrap();
+            }
         }
 
         tx.commit().expect("transaction failed");
@@ -146,28 +431,202 @@ impl DB {
      */
 
     pub fn search_bin(&self, command: String) -> Vec<BinResult> {
-        let cmd_param = format!("%{}%", command);
-        let mut stmt = self
-            .conn
-            .prepare("SELECT bins, code FROM modules WHERE bins LIKE ?")
-            .unwrap();
+        self.facts("bins", "f.name=?", &command)
+            .into_iter()
+            .map(bin_result)
+            .collect()
+    }
 
-        stmt.query_map(params![cmd_param], |row| {
-            let row_bin_col: String = row.get(0).unwrap();
-            let row_bins: Vec<String> = row_bin_col.split(":").map(|x| x.to_string()).collect();
+    /*
+     * search_man searches the database for a man or info page
+     */
 
-            if row_bins.contains(&command) {
-                return Ok(Some(BinResult {
-                    command: command.clone(),
-                    code: row.get(1).unwrap(),
-                }));
+    pub fn search_man(&self, page: String) -> Vec<ManResult> {
+        self.search_list("mans", "page", &page)
+            .into_iter()
+            .map(|code| ManResult {
+                code,
+                page: page.clone(),
+            })
+            .collect()
+    }
+
+    /*
+     * search_header searches the database for a header, as written in an #include
+     */
+
+    pub fn search_header(&self, header: String) -> Vec<HeaderResult> {
+        self.search_list("headers", "header", &header)
+            .into_iter()
+            .map(|code| HeaderResult {
+                code,
+                header: header.clone(),
+            })
+            .collect()
+    }
+
+    /*
+     * search_py searches the database for a top-level python package
+     * dotted names are reduced to their top-level package first
+     */
+
+    pub fn search_py(&self, package: String) -> Vec<PyResult> {
+        let top = package.split('.').next().unwrap().to_string();
+
+        self.search_list("pys", "package", &top)
+            .into_iter()
+            .map(|code| PyResult {
+                code,
+                package: top.clone(),
+            })
+            .collect()
+    }
+
+    /*
+     * search_pkgconfig searches the database for a pkg-config package
+     */
+
+    pub fn search_pkgconfig(&self, name: String) -> Vec<PkgConfigResult> {
+        first_per_module(self.facts("pkgconfigs", "f.name=?", &name))
+            .into_iter()
+            .map(|r| PkgConfigResult {
+                code: r.code,
+                name: name.clone(),
+                version: r.fields[1].clone(),
+            })
+            .collect()
+    }
+
+    /*
+     * search_cmake searches the database for a find_package() package.
+     * cmake accepts both <Name>Config.cmake and <name>-config.cmake, so the
+     * match ignores case.
+     */
+
+    pub fn search_cmake(&self, package: String) -> Vec<CMakeResult> {
+        first_per_module(self.facts("cmakes", "f.package=? COLLATE NOCASE", &package))
+            .into_iter()
+            .map(|r| CMakeResult {
+                code: r.code,
+                package: r.fields[0].clone(),
+            })
+            .collect()
+    }
+
+    /*
+     * search_lib searches the database for a shared library, ignoring versions.
+     * a module matching the exact name reports it, otherwise its first variant.
+     * globs like libhdf5.so* and bare names like hdf5 are accepted too.
+     */
+
+    pub fn search_lib(&self, lib: String) -> Vec<LibResult> {
+        let mut lib = lib.trim_end_matches('*').trim_end_matches('.').to_string();
+
+        if !lib.starts_with("lib") && !lib.contains(".so") {
+            lib = format!("lib{}", lib);
+        }
+
+        let base = match analysis::soname_base(&lib) {
+            Some(base) => base.to_string(),
+            None => format!("{}.so", lib),
+        };
+
+        let rows: Vec<FactRow> = self
+            .facts("libs", "f.lib LIKE ?", &format!("{}%", base))
+            .into_iter()
+            .filter(|r| analysis::soname_base(&r.fields[0]) == Some(base.as_str()))
+            .collect();
+
+        let mut out: Vec<(String, LibResult)> = Vec::new();
+
+        for r in rows {
+            match out.iter_mut().find(|(module, _)| *module == r.module) {
+                Some((_, found)) => {
+                    if r.fields[0] == lib {
+                        found.lib = lib.clone();
+                    }
+                }
+                None => out.push((
+                    r.module,
+                    LibResult {
+                        code: r.code,
+                        lib: r.fields[0].clone(),
+                        chain: r.chain,
+                    },
+                )),
             }
+        }
+
+        out.into_iter().map(|(_, r)| r).collect()
+    }
+
+    /*
+     * search_env searches the database for modules touching an environment
+     * variable. every operation on it is reported, in modulefile order.
+     */
+
+    pub fn search_env(&self, var: String) -> Vec<EnvResult> {
+        self.facts("envs", "f.var=?", &var)
+            .into_iter()
+            .map(|r| EnvResult {
+                code: r.code,
+                op: r.fields[0].clone(),
+                value: r.fields[2].clone(),
+            })
+            .collect()
+    }
+
+    /*
+     * search_fact searches any analyzer's table on its first field
+     */
+
+    pub fn search_fact(&self, table: &str, field: &str, value: &str) -> Vec<(String, Vec<String>)> {
+        self.facts(table, &format!("f.{}=?", field), value)
+            .into_iter()
+            .map(|r| (r.code, r.fields))
+            .collect()
+    }
+
+    /*
+     * search_list returns the codes of modules with a fact matching an item
+     */
+
+    fn search_list(&self, table: &str, field: &str, item: &str) -> Vec<String> {
+        first_per_module(self.facts(table, &format!("f.{}=?", field), item))
+            .into_iter()
+            .map(|r| r.code)
+            .collect()
+    }
+
+    /*
+     * facts returns the facts in a table matching a condition on the fact
+     * (aliased as f), joined with their module. hidden modules are left out.
+     */
 
-            Ok(None)
+    fn facts(&self, table: &str, condition: &str, value: &str) -> Vec<FactRow> {
+        let mut stmt = self
+            .conn
+            .prepare(&format!(
+                "SELECT m.path, m.code, m.chain, m.isdefault, m.kind, m.activate, f.* FROM {} f JOIN modules m ON m.path=f.module WHERE {} AND m.hidden=0 ORDER BY m.code, f.rowid",
+                table, condition
+            ))
+            .unwrap();
+
+        let count = stmt.column_count();
+
+        stmt.query_map(params![value], |row| {
+            Ok(FactRow {
+                module: row.get(0).unwrap(),
+                code: row.get(1).unwrap(),
+                chain: split_list(row.get(2).unwrap()),
+                default: row.get(3).unwrap(),
+                kind: row.get(4).unwrap(),
+                activate: row.get(5).unwrap(),
+                fields: (7..count).map(|i| row.get(i).unwrap()).collect(),
+            })
         })
         .unwrap()
         .filter_map(Result::ok)
-        .filter_map(|x| x)
         .collect()
     }
 
@@ -176,42 +635,286 @@ impl DB {
      */
 
     pub fn search_bin_fuzzy(&self, command: String) -> Vec<BinResult> {
-        let cmd_param = format!("%{}%", command);
+        self.facts("bins", "f.name LIKE ?", &format!("%{}%", command))
+            .into_iter()
+            .map(bin_result)
+            .collect()
+    }
+
+    /*
+     * dependencies returns every indexed module with its default flag and
+     * declared dependencies
+     */
+
+    pub fn dependencies(&self) -> Vec<(String, bool, Vec<String>)> {
         let mut stmt = self
             .conn
-            .prepare("SELECT bins, code FROM modules WHERE bins LIKE ?")
+            .prepare("SELECT m.path, m.code, m.isdefault, f.name FROM modules m LEFT JOIN deps f ON f.module=m.path ORDER BY m.code, f.rowid")
             .unwrap();
 
-        let vecs: Vec<Vec<BinResult>> = stmt
-            .query_map(params![cmd_param], |row| {
-                let row_bin_col: String = row.get(0).unwrap();
-                let row_bins: Vec<String> = row_bin_col.split(":").map(|x| x.to_string()).collect();
+        let rows: Vec<(String, String, bool, Option<String>)> = stmt
+            .query_map(NO_PARAMS, |row| {
+                Ok((
+                    row.get(0).unwrap(),
+                    row.get(1).unwrap(),
+                    row.get(2).unwrap(),
+                    row.get(3).unwrap(),
+                ))
+            })
+            .unwrap()
+            .filter_map(Result::ok)
+            .collect();
 
-                let mut out = Vec::new();
-                let row_code: String = row.get(1).unwrap();
+        let mut out: Vec<(String, String, bool, Vec<String>)> = Vec::new();
 
-                for bin in row_bins {
-                    if bin.contains(&command) {
-                        out.push(BinResult {
-                            command: bin,
-                            code: row_code.clone(),
-                        });
-                    }
-                }
+        for (path, code, default, dep) in rows {
+            if out.last().map(|x| x.0 != path).unwrap_or(true) {
+                out.push((path, code, default, Vec::new()));
+            }
+
+            out.last_mut().unwrap().3.extend(dep);
+        }
+
+        out.into_iter()
+            .map(|(_, code, default, deps)| (code, default, deps))
+            .collect()
+    }
+
+    /*
+     * constraints returns the conflicts and families declared by a module
+     */
+
+    pub fn constraints(&self, code: &str) -> (Vec<String>, Vec<String>) {
+        (
+            self.module_facts("conflicts", code),
+            self.module_facts("families", code),
+        )
+    }
+
+    /* spec returns the spack spec of a module, for spack installs */
+    pub fn spec(&self, code: &str) -> Option<String> {
+        self.module_facts("spack", code).into_iter().next()
+    }
+
+    /* toolchain returns the easybuild toolchain a module was built with */
+    pub fn toolchain(&self, code: &str) -> Option<String> {
+        self.module_facts("easybuild", code).into_iter().next()
+    }
 
-                Ok(out)
+    /*
+     * module_facts returns the first field of a module's facts in a table
+     */
+
+    fn module_facts(&self, table: &str, code: &str) -> Vec<String> {
+        let mut stmt = self
+            .conn
+            .prepare(&format!(
+                "SELECT f.* FROM {} f JOIN modules m ON m.path=f.module WHERE m.code=? ORDER BY f.rowid",
+                table
+            ))
+            .unwrap();
+
+        stmt.query_map(params![code], |row| row.get(1))
+            .unwrap()
+            .filter_map(Result::ok)
+            .collect()
+    }
+
+    /*
+     * info returns the indexed details of every module matching a name or code
+     */
+
+    pub fn info(&self, name: &str) -> Vec<ModuleInfo> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT m.path, m.code, m.chain, m.isdefault, m.aliases, m.kind, m.activate, f.description, f.category, f.homepage, f.keywords, f.help, e.toolchain, e.description, e.homepage, e.dependencies FROM modules m LEFT JOIN meta f ON f.module=m.path LEFT JOIN easybuild e ON e.module=m.path WHERE m.code=?1 OR m.code LIKE ?2 ORDER BY m.code")
+            .unwrap();
+
+        let mut res: Vec<ModuleInfo> = stmt
+            .query_map(params![name, format!("{}/%", name)], |row| {
+                let text = |i: usize| -> String {
+                    row.get::<_, Option<String>>(i).unwrap().unwrap_or_default()
+                };
+
+                /* easyconfigs fill in what the modulefile doesn't say */
+                let or = |a: String, b: String| if a.is_empty() { b } else { a };
+
+                Ok(ModuleInfo {
+                    path: row.get(0).unwrap(),
+                    code: row.get(1).unwrap(),
+                    bins: Vec::new(),
+                    description: or(text(7), text(13)),
+                    spec: String::new(),
+                    toolchain: text(12),
+                    dependencies: text(15).split_whitespace().map(|x| x.to_string()).collect(),
+                    category: text(8),
+                    homepage: or(text(9), text(14)),
+                    keywords: text(10),
+                    help: text(11),
+                    chain: split_list(row.get(2).unwrap()),
+                    default: row.get(3).unwrap(),
+                    aliases: split_list(row.get(4).unwrap()),
+                    kind: row.get(5).unwrap(),
+                    activate: row.get(6).unwrap(),
+                })
             })
             .unwrap()
             .filter_map(Result::ok)
             .collect();
 
-        vecs.into_iter().flatten().collect()
+        let mut bins = self
+            .conn
+            .prepare("SELECT name FROM bins WHERE module=? ORDER BY rowid")
+            .unwrap();
+
+        for m in &mut res {
+            m.bins = bins
+                .query_map(params![m.path], |row| row.get(0))
+                .unwrap()
+                .filter_map(Result::ok)
+                .collect();
+            m.spec = self.spec(&m.code).unwrap_or_default();
+        }
+
+        res
+    }
+
+    /*
+     * hierarchy returns the modules synced under a nonce which extend the
+     * MODULEPATH, with their own prerequisite chains
+     */
+
+    pub fn hierarchy(&self, nonce: u32) -> Vec<(String, Vec<String>, Vec<String>)> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT m.path, m.code, m.chain, f.dir FROM modulepaths f JOIN modules m ON m.path=f.module WHERE m.nonce=? ORDER BY m.code, f.rowid")
+            .unwrap();
+
+        let rows: Vec<(String, String, String, String)> = stmt
+            .query_map(params![nonce], |row| {
+                Ok((
+                    row.get(0).unwrap(),
+                    row.get(1).unwrap(),
+                    row.get(2).unwrap(),
+                    row.get(3).unwrap(),
+                ))
+            })
+            .unwrap()
+            .filter_map(Result::ok)
+            .collect();
+
+        let mut out: Vec<(String, String, Vec<String>, Vec<String>)> = Vec::new();
+
+        for (path, code, chain, dir) in rows {
+            if out.last().map(|x| x.0 != path).unwrap_or(true) {
+                out.push((path, code, split_list(chain), Vec::new()));
+            }
+
+            out.last_mut().unwrap().3.push(dir);
+        }
+
+        out.into_iter()
+            .map(|(_, code, chain, dirs)| (code, chain, dirs))
+            .collect()
+    }
+
+    /*
+     * skipped returns the files passed over by the last sync, with the reason
+     */
+
+    pub fn skipped(&self) -> Vec<(String, String)> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT path, reason FROM skipped ORDER BY path")
+            .unwrap();
+
+        stmt.query_map(NO_PARAMS, |row| {
+            Ok((row.get(0).unwrap(), row.get(1).unwrap()))
+        })
+        .unwrap()
+        .filter_map(Result::ok)
+        .collect()
     }
 
     /*
      * purge() clears out the whole module table
      */
     pub fn purge(&self) {
-        self.conn.execute("DELETE FROM modules", NO_PARAMS).unwrap();
+        for table in tables(&self.conn) {
+            self.conn
+                .execute(&format!("DELETE FROM {}", table), NO_PARAMS)
+                .unwrap();
+        }
+    }
+}
+
+/* split_list() splits a list column into its items */
+fn split_list(col: String) -> Vec<String> {
+    col.split(LIST_SEP)
+        .filter(|x| !x.is_empty())
+        .map(|x| x.to_string())
+        .collect()
+}
+
+/* tables() lists every table in the database */
+fn tables(conn: &Connection) -> Vec<String> {
+    let mut stmt = conn
+        .prepare("SELECT name FROM sqlite_master WHERE type='table'")
+        .unwrap();
+
+    stmt.query_map(NO_PARAMS, |row| row.get(0))
+        .unwrap()
+        .filter_map(Result::ok)
+        .collect()
+}
+
+/* columns() lists the columns of a table, in order */
+fn columns(conn: &Connection, table: &str) -> Vec<String> {
+    let mut stmt = conn
+        .prepare(&format!("PRAGMA table_info({})", table))
+        .unwrap();
+
+    stmt.query_map(NO_PARAMS, |row| row.get(1))
+        .unwrap()
+        .filter_map(Result::ok)
+        .collect()
+}
+
+/* fact_tables() lists the tables holding analyzer facts */
+fn fact_tables(conn: &Connection) -> Vec<String> {
+    tables(conn)
+        .into_iter()
+        .filter(|x| !CORE_TABLES.contains(&x.as_str()))
+        .collect()
+}
+
+/* first_per_module() keeps one matching fact per module */
+fn first_per_module(rows: Vec<FactRow>) -> Vec<FactRow> {
+    let mut out: Vec<FactRow> = Vec::new();
+
+    for r in rows {
+        if !out.iter().any(|x| x.module == r.module) {
+            out.push(r);
+        }
+    }
+
+    out
+}
+
+fn bin_result(r: FactRow) -> BinResult {
+    BinResult {
+        code: r.code,
+        path: r.module,
+        chain: r.chain,
+        default: r.default,
+        symlink: r.fields[1] == "symlink",
+        needs: r.fields[3]
+            .split_whitespace()
+            .map(|x| x.to_string())
+            .collect(),
+        target: r.fields[2].clone(),
+        command: r.fields[0].clone(),
+        kind: r.kind,
+        activate: r.activate,
     }
 }
@@ -0,0 +1,339 @@
+/*
+ * deps.rs
+ *
+ * module dependency graph
+ *
+ * dependencies are declared by name, often without a version ("gcc" instead
+ * of "gcc/9.3"). they're resolved against the index the same way a bare
+ * `module load` would pick a version: the default if one is marked (.version,
+ * .modulerc or a "default" symlink), otherwise the highest one available.
+ */
+
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet};
+
+pub struct Graph {
+    deps: BTreeMap<String, Vec<String>>,
+}
+
+impl Graph {
+    pub fn new(entries: Vec<(String, bool, Vec<String>)>) -> Graph {
+        let codes: Vec<String> = entries.iter().map(|(code, _, _)| code.clone()).collect();
+        let defaults: Vec<String> = entries
+            .iter()
+            .filter(|(_, default, _)| *default)
+            .map(|(code, _, _)| code.clone())
+            .collect();
+
+        Graph {
+            deps: entries
+                .into_iter()
+                .map(|(code, _, deps)| {
+                    let resolved = deps
+                        .iter()
+                        .map(|d| resolve(d, &codes, &defaults).unwrap_or_else(|| d.clone()))
+                        .collect();
+
+                    (code, resolved)
+                })
+                .collect(),
+        }
+    }
+
+    /* modules() returns every module in the graph */
+    pub fn modules(&self) -> Vec<String> {
+        self.deps.keys().cloned().collect()
+    }
+
+    /* find() returns the modules matching a user supplied name or code */
+    pub fn find(&self, name: &str) -> Vec<String> {
+        if self.deps.contains_key(name) {
+            return vec![name.to_string()];
+        }
+
+        let prefix = format!("{}/", name);
+
+        self.deps
+            .keys()
+            .filter(|x| x.starts_with(&prefix))
+            .cloned()
+            .collect()
+    }
+
+    /* dependents() inverts the graph: module -> modules depending on it */
+    fn dependents(&self) -> BTreeMap<String, Vec<String>> {
+        let mut out: BTreeMap<String, Vec<String>> = BTreeMap::new();
+
+        for (code, deps) in &self.deps {
+            for dep in deps {
+                out.entry(dep.clone()).or_default().push(code.clone());
+            }
+        }
+
+        out
+    }
+
+    fn edges(&self, reverse: bool) -> BTreeMap<String, Vec<String>> {
+        if reverse {
+            self.dependents()
+        } else {
+            self.deps.clone()
+        }
+    }
+
+    /*
+     * tree() renders the (reverse) dependencies of a module as an indented tree
+     */
+
+    pub fn tree(&self, root: &str, reverse: bool) -> String {
+        let edges = self.edges(reverse);
+        let mut out = String::new();
+        let mut stack = Vec::new();
+
+        self.tree_node(&edges, root, 0, &mut stack, &mut out);
+        out
+    }
+
+    fn tree_node(
+        &self,
+        edges: &BTreeMap<String, Vec<String>>,
+        node: &str,
+        depth: usize,
+        stack: &mut Vec<String>,
+        out: &mut String,
+    ) {
+        let note = if stack.iter().any(|x| x == node) {
+            " (cycle)"
+        } else if !self.deps.contains_key(node) {
+            " (not indexed)"
+        } else {
+            ""
+        };
+
+        out.push_str(&format!("{}{}{}\n", "  ".repeat(depth), node, note));
+
+        if !note.is_empty() {
+            return;
+        }
+
+        stack.push(node.to_string());
+
+        for child in edges.get(node).map(|x| x.as_slice()).unwrap_or(&[]) {
+            self.tree_node(edges, child, depth + 1, stack, out);
+        }
+
+        stack.pop();
+    }
+
+    /*
+     * dot() renders the part of the graph reachable from the roots in graphviz
+     * format. edges always point from a module to its dependency.
+     */
+
+    pub fn dot(&self, roots: &[String], reverse: bool) -> String {
+        let edges = self.edges(reverse);
+        let mut seen: BTreeSet<String> = BTreeSet::new();
+        let mut queue: Vec<String> = roots.to_vec();
+        let mut lines: BTreeSet<String> = BTreeSet::new();
+
+        while let Some(node) = queue.pop() {
+            if !seen.insert(node.clone()) {
+                continue;
+            }
+
+            lines.insert(format!("    \"{}\";", node));
+
+            for next in edges.get(&node).map(|x| x.as_slice()).unwrap_or(&[]) {
+                let (from, to) = if reverse {
+                    (next, &node)
+                } else {
+                    (&node, next)
+                };
+
+                lines.insert(format!("    \"{}\" -> \"{}\";", from, to));
+                queue.push(next.clone());
+            }
+        }
+
+        let mut out = String::from("digraph mii {\n");
+
+        for line in lines {
+            out.push_str(&line);
+            out.push('\n');
+        }
+
+        out.push_str("}\n");
+        out
+    }
+}
+
+/* matches() checks if a module name like "openmpi" refers to a code */
+pub fn matches(name: &str, code: &str) -> bool {
+    code == name || (code.starts_with(name) && code[name.len()..].starts_with('/'))
+}
+
+/*
+ * resolve() maps a dependency name to an indexed module code. exact codes win,
+ * then the default version below the name, otherwise the highest one.
+ */
+
+pub fn resolve(name: &str, codes: &[String], defaults: &[String]) -> Option<String> {
+    if codes.iter().any(|x| x == name) {
+        return Some(name.to_string());
+    }
+
+    if let Some(code) = defaults.iter().find(|x| matches(name, x)) {
+        return Some(code.clone());
+    }
+
+    let prefix = format!("{}/", name);
+
+    codes
+        .iter()
+        .filter(|x| x.starts_with(&prefix))
+        .max_by(|a, b| version_cmp(a, b))
+        .cloned()
+}
+
+/*
+ * version_cmp() compares module codes so that numeric parts sort numerically:
+ * gcc/9.3 < gcc/11.1
+ */
+
+pub fn version_cmp(a: &str, b: &str) -> Ordering {
+    let mut a = chunks(a).into_iter();
+    let mut b = chunks(b).into_iter();
+
+    loop {
+        let ord = match (a.next(), b.next()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(x), Some(y)) => match (x.parse::<u64>(), y.parse::<u64>()) {
+                (Ok(x), Ok(y)) => x.cmp(&y),
+                _ => x.cmp(&y),
+            },
+        };
+
+        if ord != Ordering::Equal {
+            return ord;
+        }
+    }
+}
+
+/* chunks() splits a string into runs of digits and non-digits */
+fn chunks(s: &str) -> Vec<String> {
+    let mut out: Vec<String> = Vec::new();
+    let mut last_digit = None;
+
+    for c in s.chars() {
+        let digit = c.is_ascii_digit();
+
+        if last_digit != Some(digit) {
+            out.push(String::new());
+            last_digit = Some(digit);
+        }
+
+        out.last_mut().unwrap().push(c);
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn codes(list: &[&str]) -> Vec<String> {
+        list.iter().map(|x| x.to_string()).collect()
+    }
+
+    fn graph(entries: &[(&str, bool, &[&str])]) -> Graph {
+        Graph::new(
+            entries
+                .iter()
+                .map(|(code, default, deps)| (code.to_string(), *default, codes(deps)))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn matches_names() {
+        assert!(matches("gcc", "gcc"));
+        assert!(matches("gcc", "gcc/9.3"));
+        assert!(matches("gcc/9.3", "gcc/9.3"));
+        assert!(!matches("gcc", "gcc-toolset/9"));
+        assert!(!matches("gcc/9", "gcc/9.3"));
+    }
+
+    #[test]
+    fn version_order() {
+        assert_eq!(version_cmp("foo/1.10", "foo/1.9"), Ordering::Greater);
+        assert_eq!(version_cmp("gcc/9.3", "gcc/11.1"), Ordering::Less);
+        assert_eq!(version_cmp("foo/1.0", "foo/1.0"), Ordering::Equal);
+        assert_eq!(version_cmp("foo/1.0", "foo/1.0.1"), Ordering::Less);
+    }
+
+    #[test]
+    fn resolve_names() {
+        let all = codes(&["foo/1.0", "foo/1.9", "foo/1.10", "foobar/2.0"]);
+
+        assert_eq!(resolve("foo", &all, &[]), Some("foo/1.10".to_string()));
+        assert_eq!(resolve("foo/1.9", &all, &[]), Some("foo/1.9".to_string()));
+        assert_eq!(resolve("baz", &all, &[]), None);
+        assert_eq!(
+            resolve("foo", &all, &codes(&["foo/1.0"])),
+            Some("foo/1.0".to_string())
+        );
+        assert_eq!(
+            resolve("foo/1.9", &all, &codes(&["foo/1.0"])),
+            Some("foo/1.9".to_string())
+        );
+    }
+
+    #[test]
+    fn default_dependency() {
+        let g = graph(&[
+            ("bar/1.0", false, &["foo"]),
+            ("foo/1.0", true, &[]),
+            ("foo/2.0", false, &[]),
+        ]);
+
+        assert_eq!(g.tree("bar/1.0", false), "bar/1.0\n  foo/1.0\n");
+    }
+
+    #[test]
+    fn tree() {
+        let g = graph(&[
+            ("app/1.0", false, &["mpi", "zlib"]),
+            ("mpi/4.0", false, &["gcc/9.3"]),
+            ("gcc/9.3", false, &[]),
+        ]);
+
+        assert_eq!(
+            g.tree("app/1.0", false),
+            "app/1.0\n  mpi/4.0\n    gcc/9.3\n  zlib (not indexed)\n"
+        );
+        assert_eq!(g.tree("gcc/9.3", true), "gcc/9.3\n  mpi/4.0\n    app/1.0\n");
+    }
+
+    #[test]
+    fn cycle() {
+        let g = graph(&[("a/1", false, &["b"]), ("b/1", false, &["a"])]);
+
+        assert_eq!(g.tree("a/1", false), "a/1\n  b/1\n    a/1 (cycle)\n");
+        assert_eq!(
+            g.dot(&codes(&["a/1"]), false),
+            "digraph mii {\n    \"a/1\" -> \"b/1\";\n    \"a/1\";\n    \"b/1\" -> \"a/1\";\n    \"b/1\";\n}\n"
+        );
+    }
+
+    #[test]
+    fn dot() {
+        let g = graph(&[("app/1.0", false, &["gcc"]), ("gcc/9.3", false, &[])]);
+        let expected = "digraph mii {\n    \"app/1.0\" -> \"gcc/9.3\";\n    \"app/1.0\";\n    \"gcc/9.3\";\n}\n";
+
+        assert_eq!(g.dot(&codes(&["app/1.0"]), false), expected);
+        assert_eq!(g.dot(&codes(&["gcc/9.3"]), true), expected);
+    }
+}
@@ -0,0 +1,447 @@
+/*
+ * easybuild.rs
+ *
+ * easybuild metadata enrichment
+ *
+ * easybuild leaves the easyconfig it built from in <prefix>/easybuild/, and
+ * its modules point EBROOT<NAME> at the prefix. easyconfigs are python, but
+ * only a handful of plain assignments matter here, so they're read with a
+ * small parser for python literals rather than a python interpreter:
+ *
+ *   toolchain = {'name': 'foss', 'version': '2020a'}
+ *   versionsuffix = '-Python-%(pyver)s'
+ *   dependencies = [('HTSlib', '1.10.2'), ('Python', local_pyver)]
+ */
+
+use crate::analysis::{Analyzer, Fact, Module};
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const EASYCONFIG_DIR: &str = "easybuild";
+
+/* easybuild's names for the toolchain of compilers themselves */
+const SYSTEM_TOOLCHAINS: &[&str] = &["SYSTEM", "system", "dummy"];
+
+pub struct EasyBuild;
+
+impl Analyzer for EasyBuild {
+    fn name(&self) -> &'static str {
+        "easybuild"
+    }
+
+    fn fields(&self) -> &'static [&'static str] {
+        &[
+            "toolchain",
+            "versionsuffix",
+            "description",
+            "homepage",
+            "dependencies",
+            "easyconfig",
+        ]
+    }
+
+    fn analyze(&self, module: &Module) -> Vec<Fact> {
+        /*
+         * EBROOT variables name the prefix outright, PATH is the fallback. the
+         * module's own one goes first, like EBROOTSAMTOOLS for SAMtools/1.10.
+         */
+        let own = root_var(&module.file.code);
+        let mut roots: Vec<(&str, &str)> = module
+            .effects
+            .env
+            .iter()
+            .map(|op| op.parts())
+            .filter(|(op, var, _)| *op == "set" && var.starts_with("EBROOT"))
+            .map(|(_, var, value)| (var, value))
+            .collect();
+
+        roots.sort_by_key(|(var, _)| *var != own);
+
+        let mut prefixes: Vec<String> = roots.iter().map(|(_, x)| x.to_string()).collect();
+
+        prefixes.extend(module.effects.prefixes());
+
+        for prefix in prefixes {
+            let easyconfig = match find_easyconfig(Path::new(&prefix)) {
+                Some(path) => path,
+                None => continue,
+            };
+
+            match fs::read_to_string(&easyconfig) {
+                Ok(contents) => return vec![describe(&contents, &easyconfig)],
+                Err(e) => warn!("Failed to read {}: {}", easyconfig.display(), e),
+            }
+        }
+
+        Vec::new()
+    }
+}
+
+/* root_var() is the EBROOT variable easybuild sets for a module */
+fn root_var(code: &str) -> String {
+    let name = code.split('/').next().unwrap_or_default();
+
+    format!(
+        "EBROOT{}",
+        name.to_uppercase().replace('-', "MIN").replace('+', "PLUS")
+    )
+}
+
+/* find_easyconfig() returns the easyconfig an install prefix was built from */
+fn find_easyconfig(prefix: &Path) -> Option<PathBuf> {
+    let mut found: Vec<PathBuf> = fs::read_dir(prefix.join(EASYCONFIG_DIR))
+        .ok()?
+        .flatten()
+        .map(|x| x.path())
+        .filter(|x| x.extension().map(|e| e == "eb").unwrap_or(false))
+        .collect();
+
+    found.sort();
+    found.into_iter().next()
+}
+
+fn describe(contents: &str, path: &Path) -> Fact {
+    let vars = parse(contents);
+
+    let text = |key: &str| -> String {
+        vars.get(key)
+            .map(|x| template(&x.text(&vars), &vars))
+            .unwrap_or_default()
+    };
+
+    let toolchain = match vars.get("toolchain").map(|x| x.resolve(&vars)) {
+        Some(Value::Dict(entries)) => {
+            let field = |key: &str| -> String {
+                entries
+                    .iter()
+                    .find(|(k, _)| k.text(&vars) == key)
+                    .map(|(_, v)| v.text(&vars))
+                    .unwrap_or_default()
+            };
+
+            if SYSTEM_TOOLCHAINS.contains(&field("name").as_str()) {
+                "system".to_string()
+            } else {
+                format!("{}/{}", field("name"), field("version"))
+            }
+        }
+        Some(Value::Name(_)) => "system".to_string(),
+        _ => String::new(),
+    };
+
+    /* runtime dependencies only: (name, version[, suffix[, toolchain]]) */
+    let dependencies: Vec<String> = match vars.get("dependencies").map(|x| x.resolve(&vars)) {
+        Some(Value::List(deps)) => deps
+            .iter()
+            .filter_map(|dep| match dep.resolve(&vars) {
+                Value::List(fields) if fields.len() >= 2 => {
+                    let suffix = fields.get(2).map(|x| x.text(&vars)).unwrap_or_default();
+
+                    Some(template(
+                        &format!(
+                            "{}/{}{}",
+                            fields[0].text(&vars),
+                            fields[1].text(&vars),
+                            suffix
+                        ),
+                        &vars,
+                    ))
+                }
+                _ => None,
+            })
+            .collect(),
+        _ => Vec::new(),
+    };
+
+    vec![
+        toolchain,
+        text("versionsuffix"),
+        text("description")
+            .split_whitespace()
+            .collect::<Vec<_>>()
+            .join(" "),
+        text("homepage"),
+        dependencies.join(" "),
+        path.to_string_lossy().to_string(),
+    ]
+}
+
+/*
+ * template() fills in the easyconfig templates which can be resolved from the
+ * easyconfig alone, like %(version)s. anything else is left as it is.
+ */
+
+fn template(s: &str, vars: &HashMap<String, Value>) -> String {
+    let mut out = s.to_string();
+
+    for key in &["name", "version", "versionsuffix"] {
+        if let Some(v) = vars.get(*key) {
+            out = out.replace(&format!("%({})s", key), &v.text(vars));
+        }
+    }
+
+    if let Some(v) = vars.get("name") {
+        out = out.replace("%(namelower)s", &v.text(vars).to_lowercase());
+    }
+
+    out
+}
+
+/* python values, as far as easyconfigs need them */
+#[derive(Clone)]
+enum Value {
+    Str(String),
+    List(Vec<Value>), /* lists and tuples alike */
+    Dict(Vec<(Value, Value)>),
+    Name(String), /* identifiers, numbers and anything unsupported */
+}
+
+impl Value {
+    /* resolve() looks up names assigned earlier in the easyconfig */
+    fn resolve<'a>(&'a self, vars: &'a HashMap<String, Value>) -> &'a Value {
+        match self {
+            Value::Name(n) => vars.get(n).unwrap_or(self),
+            _ => self,
+        }
+    }
+
+    fn text(&self, vars: &HashMap<String, Value>) -> String {
+        match self.resolve(vars) {
+            Value::Str(s) | Value::Name(s) => s.clone(),
+            _ => String::new(),
+        }
+    }
+}
+
+/* parse() returns the top level assignments of an easyconfig */
+fn parse(contents: &str) -> HashMap<String, Value> {
+    let mut p = Parser {
+        src: contents.chars().collect(),
+        pos: 0,
+    };
+    let mut vars = HashMap::new();
+
+    while p.skip(true) {
+        let name = p.ident();
+        p.skip(false);
+
+        if !name.is_empty() && p.peek() == Some('=') && p.peek_at(1) != Some('=') {
+            p.pos += 1;
+            let value = p.expr(false);
+            vars.insert(name, value);
+        } else {
+            /* imports, conditionals and the like */
+            p.skip_line();
+        }
+    }
+
+    vars
+}
+
+struct Parser {
+    src: Vec<char>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        self.src.get(self.pos).cloned()
+    }
+
+    fn peek_at(&self, n: usize) -> Option<char> {
+        self.src.get(self.pos + n).cloned()
+    }
+
+    /*
+     * skip() moves past whitespace and comments, stopping at newlines unless
+     * they're allowed. returns false at the end of the input.
+     */
+
+    fn skip(&mut self, newlines: bool) -> bool {
+        while let Some(c) = self.peek() {
+            match c {
+                '#' => {
+                    while self.peek().map(|x| x != '\n').unwrap_or(false) {
+                        self.pos += 1;
+                    }
+                }
+                '\\' if self.peek_at(1) == Some('\n') => self.pos += 2,
+                '\n' if !newlines => break,
+                c if c.is_whitespace() => self.pos += 1,
+                _ => break,
+            }
+        }
+
+        self.pos < self.src.len()
+    }
+
+    fn skip_line(&mut self) {
+        while let Some(c) = self.peek() {
+            self.pos += 1;
+
+            if c == '\n' {
+                break;
+            }
+        }
+    }
+
+    fn ident(&mut self) -> String {
+        let start = self.pos;
+
+        while self
+            .peek()
+            .map(|c| c.is_alphanumeric() || c == '_' || c == '.')
+            .unwrap_or(false)
+        {
+            self.pos += 1;
+        }
+
+        self.src[start..self.pos].iter().collect()
+    }
+
+    /*
+     * expr() parses a value. string concatenation (implicit or with +) is
+     * followed, % formatting and calls are parsed but ignored.
+     */
+
+    fn expr(&mut self, nested: bool) -> Value {
+        self.skip(nested);
+        let mut value = self.primary();
+
+        loop {
+            self.skip(nested);
+
+            match self.peek() {
+                Some('+') | Some('%') => {
+                    let op = self.peek();
+                    self.pos += 1;
+                    self.skip(nested);
+                    let rhs = self.primary();
+
+                    if let (Some('+'), Value::Str(l), Value::Str(r)) = (op, &value, &rhs) {
+                        value = Value::Str(format!("{}{}", l, r));
+                    }
+                }
+                Some('\'') | Some('"') => {
+                    if let (Value::Str(l), Value::Str(r)) = (&value, self.primary()) {
+                        value = Value::Str(format!("{}{}", l, r));
+                    }
+                }
+                _ => return value,
+            }
+        }
+    }
+
+    fn primary(&mut self) -> Value {
+        match self.peek() {
+            Some('\'') | Some('"') => Value::Str(self.string(false)),
+            Some('[') | Some('(') => Value::List(self.items()),
+            Some('{') => {
+                let mut entries = Vec::new();
+                let mut items = self.items().into_iter();
+
+                while let (Some(k), Some(v)) = (items.next(), items.next()) {
+                    entries.push((k, v));
+                }
+
+                Value::Dict(entries)
+            }
+            Some(_) => {
+                let name = self.ident();
+
+                match self.peek() {
+                    /* string prefixes: r'..', u'..' */
+                    Some('\'') | Some('"') if name.len() == 1 => {
+                        Value::Str(self.string(name == "r" || name == "R"))
+                    }
+                    Some('(') if !name.is_empty() => {
+                        self.items();
+                        Value::Name(name)
+                    }
+                    _ if name.is_empty() => {
+                        self.pos += 1;
+                        Value::Name(name)
+                    }
+                    _ => Value::Name(name),
+                }
+            }
+            None => Value::Name(String::new()),
+        }
+    }
+
+    /*
+     * items() parses a bracketed sequence. dict entries come back as a flat
+     * key, value, key, value list.
+     */
+
+    fn items(&mut self) -> Vec<Value> {
+        let close = match self.peek() {
+            Some('[') => ']',
+            Some('(') => ')',
+            _ => '}',
+        };
+
+        self.pos += 1;
+        let mut out = Vec::new();
+
+        loop {
+            self.skip(true);
+
+            match self.peek() {
+                None => return out,
+                Some(c) if c == close => {
+                    self.pos += 1;
+                    return out;
+                }
+                Some(',') | Some(':') => self.pos += 1,
+                Some(_) => {
+                    let start = self.pos;
+                    out.push(self.expr(true));
+
+                    /* always make progress, whatever the contents */
+                    if self.pos == start {
+                        self.pos += 1;
+                    }
+                }
+            }
+        }
+    }
+
+    fn string(&mut self, raw: bool) -> String {
+        let quote = self.peek().unwrap();
+        let triple = self.peek_at(1) == Some(quote) && self.peek_at(2) == Some(quote);
+        let mut out = String::new();
+
+        self.pos += if triple { 3 } else { 1 };
+
+        while let Some(c) = self.peek() {
+            if c == quote
+                && (!triple || (self.peek_at(1) == Some(quote) && self.peek_at(2) == Some(quote)))
+            {
+                self.pos += if triple { 3 } else { 1 };
+                break;
+            }
+
+            self.pos += 1;
+
+            if c == '\\' && !raw {
+                match self.peek() {
+                    Some('n') => out.push('\n'),
+                    Some('t') => out.push('\t'),
+                    Some('\n') => (),
+                    Some(e) => out.push(e),
+                    None => break,
+                }
+                self.pos += 1;
+            } else if c == '\n' && !triple {
+                break;
+            } else {
+                out.push(c);
+            }
+        }
+
+        out
+    }
+}
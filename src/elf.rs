@@ -0,0 +1,119 @@
+/*
+ * elf.rs
+ *
+ * runtime library requirements of ELF executables
+ *
+ * only the dynamic section matters here: the DT_NEEDED sonames, and the
+ * directories baked in with DT_RUNPATH (or the older DT_RPATH) which the
+ * dynamic linker searches first.
+ */
+
+use goblin::container::Ctx;
+use goblin::elf::dynamic::{DT_RPATH, DT_RUNPATH};
+use goblin::elf::header::header64::SIZEOF_EHDR;
+use goblin::elf::program_header::PT_DYNAMIC;
+use goblin::elf::{Dynamic, Elf, ProgramHeader};
+use goblin::strtab::Strtab;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+
+const ELF_MAGIC: &[u8] = b"\x7fELF";
+
+/* tables bigger than this aren't read, they can only be corrupt */
+const MAX_TABLE_SIZE: u64 = 16 * 1024 * 1024;
+
+pub struct Needs {
+    pub libs: Vec<String>,
+    pub runpaths: Vec<String>,
+}
+
+/*
+ * needs() reads the libraries an executable links against. returns None for
+ * anything that isn't a dynamically linked ELF file. only the header, the
+ * program headers and the dynamic section with its strings are read, however
+ * big the executable is.
+ */
+
+pub fn needs(path: &Path) -> Option<Needs> {
+    let mut f = File::open(path).ok()?;
+
+    /* scripts are common in bin dirs, so check the magic first. 64-bit headers are the largest */
+    let data = read_at(&mut f, 0, SIZEOF_EHDR as u64)?;
+
+    if !data.starts_with(ELF_MAGIC) {
+        return None;
+    }
+
+    let header = Elf::parse_header(&data).ok()?;
+    let ctx = Ctx::new(header.container().ok()?, header.endianness().ok()?);
+
+    let data = read_at(
+        &mut f,
+        header.e_phoff,
+        header.e_phnum as u64 * header.e_phentsize as u64,
+    )?;
+    let mut phdrs = ProgramHeader::parse(&data, 0, header.e_phnum as usize, ctx).ok()?;
+
+    /* the dynamic section is parsed out of a buffer of its own */
+    let dynamic = phdrs.iter_mut().find(|x| x.p_type == PT_DYNAMIC)?;
+    let data = read_at(&mut f, dynamic.p_offset, dynamic.p_filesz)?;
+    dynamic.p_offset = 0;
+
+    let dynamic = Dynamic::parse(&data, &phdrs, ctx).ok()??;
+    let data = read_at(
+        &mut f,
+        dynamic.info.strtab as u64,
+        dynamic.info.strsz as u64,
+    )?;
+    let strtab = Strtab::parse(&data, 0, data.len(), 0).ok()?;
+
+    let paths = |tag: u64| -> Vec<&str> {
+        dynamic
+            .dyns
+            .iter()
+            .filter(|x| x.d_tag == tag)
+            .filter_map(|x| strtab.get_at(x.d_val as usize))
+            .collect()
+    };
+
+    let origin = path
+        .parent()
+        .map(|x| x.to_string_lossy().to_string())
+        .unwrap_or_default();
+
+    /* RPATH is ignored by the linker when RUNPATH is present */
+    let mut runpaths = paths(DT_RUNPATH);
+
+    if runpaths.is_empty() {
+        runpaths = paths(DT_RPATH);
+    }
+
+    Some(Needs {
+        libs: dynamic
+            .get_libraries(&strtab)
+            .iter()
+            .map(|x| x.to_string())
+            .collect(),
+        runpaths: runpaths
+            .iter()
+            .flat_map(|x| x.split(':'))
+            .filter(|x| !x.is_empty())
+            .map(|x| x.replace("${ORIGIN}", &origin).replace("$ORIGIN", &origin))
+            .collect(),
+    })
+}
+
+/* read_at() reads a table of an ELF file */
+fn read_at(f: &mut File, offset: u64, len: u64) -> Option<Vec<u8>> {
+    if len > MAX_TABLE_SIZE {
+        return None;
+    }
+
+    let mut data = vec![0u8; len as usize];
+
+    f.seek(SeekFrom::Start(offset)).ok()?;
+    f.read_exact(&mut data).ok()?;
+
+    Some(data)
+}
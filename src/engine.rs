@@ -10,10 +10,15 @@
 use crate::analysis;
 use crate::crawl;
 use crate::db;
+use crate::deps;
+use crate::source;
+use crate::spider;
 
 use std::cmp;
+use std::collections::HashSet;
 use std::path::{Path, PathBuf};
 use std::sync::mpsc;
+use std::sync::Arc;
 use std::thread;
 
 use std::time::SystemTime;
@@ -25,20 +30,52 @@ pub struct Engine {
     db_conn: db::DB,
     modulepath: String,
     num_threads: usize,
+    analyzers: Arc<Vec<Box<dyn analysis::Analyzer>>>,
+    cache: Arc<Option<spider::Cache>>,
+    sources: Arc<Vec<Box<dyn source::Source>>>,
 }
 
 impl Engine {
-    pub fn new(modulepath: String, db_path: PathBuf) -> Engine {
-        db::DB::initialize(&db_path);
+    pub fn new(modulepath: String, db_path: PathBuf, analyzers: analysis::Registry) -> Engine {
+        let analyzers = analyzers.into_analyzers();
+
+        db::DB::initialize(&db_path, &analyzers);
 
         Engine {
             db_conn: db::DB::new(&db_path),
-            db_path: db_path,
-            modulepath: modulepath,
+            db_path,
+            modulepath,
             num_threads: cmp::min(num_cpus::get(), MAX_THREADS),
+            analyzers: Arc::new(analyzers),
+            cache: Arc::new(None),
+            sources: Arc::new(Vec::new()),
+        }
+    }
+
+    /*
+     * use_spider_cache() seeds the following syncs from an lmod spider cache.
+     * modules found in it aren't evaluated, but still crawled and analyzed.
+     * they're kept until their modulefile changes or a sync runs without one.
+     */
+
+    pub fn use_spider_cache(&mut self, path: &Path) {
+        match spider::Cache::load(path) {
+            Ok(cache) => self.cache = Arc::new(Some(cache)),
+            Err(e) => warn!("Ignoring spider cache {}: {}", path.display(), e),
         }
     }
 
+    /*
+     * add_source() indexes the providers found by a source on the following
+     * syncs, next to the modulefiles
+     */
+
+    pub fn add_source(&mut self, source: Box<dyn source::Source>) {
+        Arc::get_mut(&mut self.sources)
+            .expect("sources can't be added during a sync")
+            .push(source);
+    }
+
     /*
      * sync_light() performs a diff synchronization between the disk and the db.
      * it goes through all 5 phases and performs threading optimizations when
@@ -54,11 +91,69 @@ impl Engine {
     pub fn sync_light(&mut self) {
         let nonce = rand::random::<u32>();
 
+        /*
+         * hierarchical modulepaths only show up once a module extending the
+         * MODULEPATH has been analyzed, so the crawl goes level by level.
+         * each root remembers the modules which have to be loaded to reach it.
+         */
+
+        let mut seen: HashSet<String> = HashSet::new();
+        let mut roots: Vec<(String, Vec<String>)> = vec![(self.modulepath.clone(), Vec::new())];
+
+        for root in self.modulepath.split(':') {
+            seen.insert(root.to_string());
+        }
+
+        while !roots.is_empty() {
+            for (root, chain) in roots {
+                self.sync_root(root, chain, nonce);
+            }
+
+            roots = Vec::new();
+
+            for (code, chain, modulepaths) in self.db_conn.hierarchy(nonce) {
+                for path in modulepaths {
+                    if seen.insert(path.clone()) {
+                        debug!("Following MODULEPATH extension {} from {}", path, code);
+
+                        let mut next = chain.clone();
+                        next.push(code.clone());
+                        roots.push((path, next));
+                    }
+                }
+            }
+        }
+
+        for i in 0..self.sources.len() {
+            debug!("Crawling source {}..", self.sources[i].name());
+            let providers = self.sources[i].crawl();
+            self.sync_files(providers, Some(i), nonce);
+        }
+
+        debug!("Starting orphan phase..");
+        self.db_conn.flush_orphans(nonce);
+
+        debug!("All done!");
+    }
+
+    /*
+     * sync_root() runs the crawl, verify and analysis phases for one
+     * MODULEPATH level. modules found are tagged with the chain of modules
+     * needed to make them visible.
+     */
+
+    fn sync_root(&mut self, modulepath: String, chain: Vec<String>, nonce: u32) {
         /* crawl phase: singlethreaded */
 
         debug!("Starting crawl phase.");
         let crawl_time = SystemTime::now();
-        let files = crawl::crawl_sync(self.modulepath.clone());
+        let (mut files, skipped) = crawl::crawl_sync(modulepath);
+        self.db_conn.update_skipped(&skipped, nonce);
+
+        for f in &mut files {
+            f.chain = chain.clone();
+        }
+
         debug!(
             "Finished crawl phase in {} ms.",
             SystemTime::now()
@@ -67,6 +162,16 @@ impl Engine {
                 .as_millis()
         );
 
+        self.sync_files(files, None, nonce);
+    }
+
+    /*
+     * sync_files() runs the verify and analysis phases over crawled files,
+     * re-analyzing the ones which changed since the last sync. files found
+     * by a source get their effects from it.
+     */
+
+    fn sync_files(&mut self, files: Vec<crawl::ModuleFile>, source: Option<usize>, nonce: u32) {
         /* verify phase: multithreaded! */
 
         debug!("Starting verify phase ({})..", files.len());
@@ -88,11 +193,12 @@ impl Engine {
             let db_copy = self.db_path.clone();
             let chunk_copy = chunk.to_owned(); /* necessary as of right now. probably slow with large number of modules... */
             let tx_copy = tx.clone();
+            let seeded = self.cache.is_some();
 
             workers.push(thread::spawn(move || {
                 let mut db = db::DB::new(Path::new(&db_copy));
                 tx_copy
-                    .send(db.compare_modules(chunk_copy.to_vec(), nonce))
+                    .send(db.compare_modules(chunk_copy.to_vec(), nonce, seeded))
                     .unwrap();
             }));
         }
@@ -133,18 +239,40 @@ impl Engine {
         for chunk in verify_results.chunks(analysis_chunk_size) {
             let db_copy = self.db_path.clone();
             let chunk_copy = chunk.to_owned();
+            let analyzers = self.analyzers.clone();
+            let cache = self.cache.clone();
+            let sources = self.sources.clone();
 
             analysis_workers.push(thread::spawn(move || {
                 let mut db = db::DB::new(Path::new(&db_copy));
-                db.update_modules(
-                    &chunk_copy
-                        .to_vec()
-                        .into_iter()
-                        .map(|x| analysis::analyze(x))
-                        .filter_map(Result::ok)
-                        .collect(),
-                    nonce,
-                );
+                let res: Vec<analysis::Info> = chunk_copy
+                    .into_iter()
+                    .map(|x| {
+                        if let Some(s) = source {
+                            let effects = sources[s].effects(&x);
+                            return analysis::analyze_effects(x, effects, &analyzers);
+                        }
+
+                        /*
+                         * the spider cache only records some of the effects. modules seeded
+                         * from it are marked, and evaluated in full by the first sync
+                         * without a cache.
+                         */
+
+                        match cache.as_ref().as_ref().and_then(|c| c.effects(&x)) {
+                            Some(effects) => {
+                                analysis::analyze_effects(x, effects, &analyzers).map(|mut info| {
+                                    info.seeded = true;
+                                    info
+                                })
+                            }
+                            None => analysis::analyze(x, &analyzers),
+                        }
+                    })
+                    .filter_map(Result::ok)
+                    .collect();
+
+                db.update_modules(&res, nonce);
             }));
         }
 
@@ -159,21 +287,152 @@ impl Engine {
                 .unwrap()
                 .as_millis()
         );
-        debug!("Starting orphan phase..");
-        self.db_conn.flush_orphans(nonce);
-
-        debug!("All done!");
     }
 
     pub fn destroy_db(&self) {
         self.db_conn.purge();
     }
 
+    /*
+     * search_bin_exact() collapses symlinked providers: a command symlinked
+     * into other bin dirs is only reported by the module owning the real file,
+     * or by the first module linking to it.
+     */
+
     pub fn search_bin_exact(&self, cmd: String) -> Vec<db::BinResult> {
-        self.db_conn.search_bin(cmd)
+        let mut res = self.db_conn.search_bin(cmd);
+
+        /* other providers are only offered when no modulefile has the command */
+        if res.iter().any(|r| r.activate.is_empty()) {
+            res.retain(|r| r.activate.is_empty());
+        }
+
+        let mut seen: HashSet<String> = res
+            .iter()
+            .filter(|r| !r.symlink)
+            .map(|r| r.target.clone())
+            .collect();
+
+        res.retain(|r| !r.symlink || seen.insert(r.target.clone()));
+        res
     }
 
     pub fn search_bin_fuzzy(&self, cmd: String) -> Vec<db::BinResult> {
         self.db_conn.search_bin_fuzzy(cmd)
     }
+
+    pub fn search_man(&self, page: String) -> Vec<db::ManResult> {
+        self.db_conn.search_man(page)
+    }
+
+    pub fn search_lib(&self, lib: String) -> Vec<db::LibResult> {
+        self.db_conn.search_lib(lib)
+    }
+
+    pub fn search_py(&self, package: String) -> Vec<db::PyResult> {
+        self.db_conn.search_py(package)
+    }
+
+    pub fn search_pkgconfig(&self, name: String) -> Vec<db::PkgConfigResult> {
+        self.db_conn.search_pkgconfig(name)
+    }
+
+    pub fn search_cmake(&self, package: String) -> Vec<db::CMakeResult> {
+        self.db_conn.search_cmake(package)
+    }
+
+    pub fn search_header(&self, header: String) -> Vec<db::HeaderResult> {
+        self.db_conn.search_header(header)
+    }
+
+    pub fn skipped(&self) -> Vec<(String, String)> {
+        self.db_conn.skipped()
+    }
+
+    /*
+     * search_fact() searches the facts of any analyzer by their first field
+     * returns None for unknown analyzers
+     */
+
+    pub fn search_fact(&self, name: &str, value: &str) -> Option<Vec<(String, Vec<String>)>> {
+        self.analyzers
+            .iter()
+            .find(|a| a.name() == name)
+            .map(|a| self.db_conn.search_fact(a.name(), a.fields()[0], value))
+    }
+
+    pub fn search_env(&self, var: String) -> Vec<db::EnvResult> {
+        self.db_conn.search_env(var)
+    }
+
+    /*
+     * find_requires() picks a module for every library a command needs from
+     * outside its own module. exact soname matches win, then the highest
+     * version of a provider.
+     */
+
+    pub fn find_requires(&self, r: &db::BinResult) -> Vec<db::LibResult> {
+        let mut out: Vec<db::LibResult> = Vec::new();
+
+        for soname in &r.needs {
+            let mut providers: Vec<db::LibResult> = self
+                .db_conn
+                .search_lib(soname.clone())
+                .into_iter()
+                .filter(|x| x.code != r.code)
+                .collect();
+
+            providers.sort_by(|a, b| {
+                (a.lib == *soname)
+                    .cmp(&(b.lib == *soname))
+                    .then_with(|| deps::version_cmp(&a.code, &b.code))
+            });
+
+            match providers.pop() {
+                Some(p) if !out.iter().any(|x| x.code == p.code) => out.push(p),
+                Some(_) => (),
+                None => debug!("No module provides {} for {}", soname, r.command),
+            }
+        }
+
+        out
+    }
+
+    /*
+     * find_conflicts() lists the loaded modules which would stop a module from
+     * loading: a conflict declared by either side, or a shared family.
+     */
+
+    pub fn find_conflicts(&self, code: &str, loaded: &[String]) -> Vec<String> {
+        let (conflicts, families) = self.db_conn.constraints(code);
+
+        loaded
+            .iter()
+            .filter(|l| *l != code)
+            .filter(|l| {
+                let (l_conflicts, l_families) = self.db_conn.constraints(l);
+
+                conflicts.iter().any(|c| deps::matches(c, l))
+                    || l_conflicts.iter().any(|c| deps::matches(c, code))
+                    || families.iter().any(|f| l_families.contains(f))
+            })
+            .cloned()
+            .collect()
+    }
+
+    pub fn module_spec(&self, code: &str) -> Option<String> {
+        self.db_conn.spec(code)
+    }
+
+    pub fn module_toolchain(&self, code: &str) -> Option<String> {
+        self.db_conn.toolchain(code)
+    }
+
+    pub fn module_info(&self, name: &str) -> Vec<db::ModuleInfo> {
+        self.db_conn.info(name)
+    }
+
+    pub fn deps_graph(&self) -> deps::Graph {
+        deps::Graph::new(self.db_conn.dependencies())
+    }
 }
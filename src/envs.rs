@@ -0,0 +1,127 @@
+/*
+ * envs.rs
+ *
+ * conda environments and virtualenvs as providers
+ *
+ * plenty of tools live in shared environments rather than modules. the roots
+ * in MII_ENVPATH are searched for environments, which are indexed alongside
+ * the modulefiles: an environment is its prefix, and "loading" it means
+ * running its activation command.
+ *
+ *   /opt/conda            a conda install: the base env, and everything in envs/
+ *   /opt/conda/envs       a directory of conda envs
+ *   /shared/venvs         a directory of virtualenvs
+ */
+
+use crate::analysis::{Effects, EnvOp};
+use crate::crawl::{ModuleFile, ModuleType};
+use crate::source::{self, Source};
+
+use std::fs;
+use std::path::Path;
+
+/* kinds of provider */
+const CONDA: &str = "conda";
+const VENV: &str = "venv";
+
+/* files marking an environment prefix */
+const CONDA_MARKER: &str = "conda-meta/history";
+const VENV_MARKER: &str = "pyvenv.cfg";
+
+/* Envs is the source of environments in a colon-separated list of roots */
+pub struct Envs {
+    roots: String,
+}
+
+impl Envs {
+    pub fn new(roots: String) -> Envs {
+        Envs { roots }
+    }
+}
+
+impl Source for Envs {
+    fn name(&self) -> &'static str {
+        "envs"
+    }
+
+    /* a root can be an environment itself, or a directory of them */
+    fn crawl(&self) -> Vec<ModuleFile> {
+        let mut out: Vec<ModuleFile> = Vec::new();
+
+        for root in self.roots.split(':').filter(|x| !x.is_empty()) {
+            let root = Path::new(root);
+            let mut candidates = vec![root.to_path_buf()];
+
+            candidates.extend(source::children(root));
+            candidates.extend(source::children(&root.join("envs")));
+
+            for dir in candidates {
+                if let Some(env) = environment(&dir) {
+                    if !out.iter().any(|x| x.path == env.path) {
+                        debug!("Found environment {} in {}", env.code, dir.display());
+                        out.push(env);
+                    }
+                }
+            }
+        }
+
+        out
+    }
+
+    /* activating an environment sets its prefix variable and puts bin/ first */
+    fn effects(&self, env: &ModuleFile) -> Effects {
+        let var = match env.modtype {
+            ModuleType::PROVIDER(CONDA) => "CONDA_PREFIX",
+            _ => "VIRTUAL_ENV",
+        };
+
+        let prefix = env.path.to_string_lossy().to_string();
+        let mut effects = Effects::default();
+
+        effects
+            .env
+            .push(EnvOp::Set(var.to_string(), prefix.clone()));
+        effects.env.push(EnvOp::Prepend(
+            "PATH".to_string(),
+            format!("{}/bin", prefix),
+        ));
+
+        effects
+    }
+}
+
+fn environment(dir: &Path) -> Option<ModuleFile> {
+    let (kind, marker, activate) = if dir.join(CONDA_MARKER).is_file() {
+        (
+            CONDA,
+            CONDA_MARKER,
+            format!("conda activate {}", source::quote(dir)),
+        )
+    } else if dir.join(VENV_MARKER).is_file() {
+        (
+            VENV,
+            VENV_MARKER,
+            format!("source {}", source::quote(&dir.join("bin/activate"))),
+        )
+    } else {
+        return None;
+    };
+
+    let name = dir.file_name()?.to_string_lossy();
+
+    Some(ModuleFile {
+        path: dir.to_path_buf(),
+        code: format!("{}:{}", kind, name),
+        modtype: ModuleType::PROVIDER(kind),
+        /* conda appends to its history on every change, pip only touches bin/ */
+        hash: Some(source::hash(
+            dir,
+            fs::read(dir.join(marker)).unwrap_or_default(),
+        )),
+        chain: Vec::new(),
+        default: false,
+        hidden: false,
+        aliases: Vec::new(),
+        activate: Some(activate),
+    })
+}
@@ -0,0 +1,681 @@
+/*
+ * lmod.rs
+ *
+ * sandboxed lua evaluator for lmod modulefiles
+ *
+ * modulefiles are executed in an embedded lua interpreter with only the
+ * side-effect-free parts of the standard library loaded. the lmod api is
+ * stubbed out, and every environment change is recorded instead of applied.
+ */
+
+use crate::analysis::{Effects, EnvOp};
+use crate::crawl;
+
+use mlua::{ChunkMode, HookTriggers, Lua, LuaOptions, MultiValue, StdLib, Table, Value, Variadic};
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::env;
+use std::path::Path;
+use std::rc::Rc;
+
+/* limits for misbehaving modulefiles: instructions are counted in batches */
+const INSTRUCTION_BATCH: u32 = 10000;
+const MAX_INSTRUCTION_BATCHES: u32 = 1000;
+const MEMORY_LIMIT: usize = 64 * 1024 * 1024;
+
+/* lmod api functions which have no effect on the index */
+const NOOP_FUNCTIONS: &[&str] = &[
+    "unload",
+    "always_unload",
+    "add_property",
+    "remove_property",
+    "extensions",
+    "remove_path",
+    "set_alias",
+    "unset_alias",
+    "set_shell_function",
+    "unset_shell_function",
+    "execute",
+    "source_sh",
+    "complete",
+    "uncomplete",
+    "inherit",
+    "requireFullName",
+    "LmodMessage",
+    "LmodWarning",
+    "print",
+];
+
+/*
+ * lmod api functions which declare dependencies. the _any variants only
+ * need one of their arguments, so just the first is recorded.
+ */
+
+const DEP_FUNCTIONS: &[(&str, bool)] = &[
+    ("load", false),
+    ("try_load", false),
+    ("try_add", false),
+    ("always_load", false),
+    ("depends_on", false),
+    ("prereq", false),
+    ("load_any", true),
+    ("prereq_any", true),
+];
+
+/* lmod version constraint helpers, which resolve to just the module name */
+const CONSTRAINT_FUNCTIONS: &[&str] = &["atleast", "atmost", "between", "latest"];
+
+/* lmod api functions which always answer false during analysis */
+const FALSE_FUNCTIONS: &[&str] = &[
+    "isloaded",
+    "isAvail",
+    "isPending",
+    "userInGroups",
+    "haveDynamicMPATH",
+];
+
+struct State {
+    env: HashMap<String, String>,
+    effects: Effects,
+}
+
+/*
+ * eval() runs a lua modulefile in load mode and returns the recorded effects
+ * errors stop the modulefile, but effects recorded so far are kept
+ */
+
+pub fn eval(contents: &str, file: &crawl::ModuleFile) -> Effects {
+    let state = Rc::new(RefCell::new(State {
+        env: env::vars().collect(),
+        effects: Effects::default(),
+    }));
+
+    if let Err(e) = run(contents, file, &state) {
+        debug!("Stopped evaluating {}: {}", file.path.display(), e);
+    }
+
+    /* the interpreter is gone by now, so the state has a single owner */
+    match Rc::try_unwrap(state) {
+        Ok(state) => state.into_inner().effects,
+        Err(_) => Effects::default(),
+    }
+}
+
+/* is_lua() checks that a file compiles as lua, without running any of it */
+pub fn is_lua(contents: &str) -> bool {
+    match Lua::new_with(StdLib::NONE, LuaOptions::default()) {
+        Ok(lua) => lua
+            .load(source(contents))
+            .set_mode(ChunkMode::Text)
+            .into_function()
+            .is_ok(),
+        Err(_) => false,
+    }
+}
+
+/*
+ * source() drops a shebang-style first line, which lua only skips when it
+ * loads files itself. the newline is kept so line numbers still match.
+ */
+
+fn source(contents: &str) -> &str {
+    if contents.starts_with('#') {
+        contents.find('\n').map(|i| &contents[i..]).unwrap_or("")
+    } else {
+        contents
+    }
+}
+
+fn run(contents: &str, file: &crawl::ModuleFile, state: &Rc<RefCell<State>>) -> mlua::Result<()> {
+    let lua = Lua::new_with(
+        StdLib::STRING | StdLib::TABLE | StdLib::MATH | StdLib::OS,
+        LuaOptions::default(),
+    )?;
+
+    lua.set_memory_limit(MEMORY_LIMIT)?;
+
+    let batches = Cell::new(0);
+    lua.set_hook(
+        HookTriggers::new().every_nth_instruction(INSTRUCTION_BATCH),
+        move |_, _| {
+            batches.set(batches.get() + 1);
+
+            if batches.get() > MAX_INSTRUCTION_BATCHES {
+                return Err(mlua::Error::RuntimeError(
+                    "instruction limit exceeded".to_string(),
+                ));
+            }
+
+            Ok(())
+        },
+    );
+
+    let globals = lua.globals();
+
+    sandbox(&lua, &globals, state)?;
+    register_api(&lua, &globals, file, state)?;
+
+    lua.load(source(contents))
+        .set_name(file.path.to_string_lossy())
+        .exec()
+}
+
+/* sandbox() strips anything that could touch the outside world */
+fn sandbox(lua: &Lua, globals: &Table, state: &Rc<RefCell<State>>) -> mlua::Result<()> {
+    for name in &["dofile", "loadfile", "require", "io", "debug", "package"] {
+        globals.set(*name, Value::Nil)?;
+    }
+
+    let real_os: Table = globals.get("os")?;
+    let os = lua.create_table()?;
+
+    for name in &["date", "time", "clock", "difftime"] {
+        os.set(*name, real_os.get::<_, Value>(*name)?)?;
+    }
+
+    let st = state.clone();
+    os.set(
+        "getenv",
+        lua.create_function(move |_, var: String| Ok(st.borrow().env.get(&var).cloned()))?,
+    )?;
+
+    globals.set("os", os)
+}
+
+fn register_api(
+    lua: &Lua,
+    globals: &Table,
+    file: &crawl::ModuleFile,
+    state: &Rc<RefCell<State>>,
+) -> mlua::Result<()> {
+    for name in NOOP_FUNCTIONS {
+        globals.set(*name, lua.create_function(|_, _: MultiValue| Ok(()))?)?;
+    }
+
+    for name in FALSE_FUNCTIONS {
+        globals.set(*name, lua.create_function(|_, _: MultiValue| Ok(false))?)?;
+    }
+
+    for (name, any) in DEP_FUNCTIONS {
+        let st = state.clone();
+        let any = *any;
+
+        globals.set(
+            *name,
+            lua.create_function(move |_, args: MultiValue| {
+                let names = strings(args);
+                let names = if any {
+                    &names[..names.len().min(1)]
+                } else {
+                    &names[..]
+                };
+
+                st.borrow_mut().effects.deps.extend(names.iter().cloned());
+                Ok(())
+            })?,
+        )?;
+    }
+
+    let st = state.clone();
+    globals.set(
+        "whatis",
+        lua.create_function(move |_, args: MultiValue| {
+            st.borrow_mut().effects.whatis.push(strings(args).concat());
+            Ok(())
+        })?,
+    )?;
+
+    let st = state.clone();
+    globals.set(
+        "help",
+        lua.create_function(move |_, args: MultiValue| {
+            st.borrow_mut()
+                .effects
+                .help
+                .push_str(&strings(args).concat());
+            Ok(())
+        })?,
+    )?;
+
+    /* modulerc functions */
+
+    let st = state.clone();
+    globals.set(
+        "module_version",
+        lua.create_function(move |_, args: MultiValue| {
+            let args = strings(args);
+
+            if let Some((module, names)) = args.split_first() {
+                for name in names {
+                    st.borrow_mut()
+                        .effects
+                        .versions
+                        .push((module.clone(), name.clone()));
+                }
+            }
+            Ok(())
+        })?,
+    )?;
+
+    let st = state.clone();
+    globals.set(
+        "module_alias",
+        lua.create_function(move |_, args: MultiValue| {
+            let args = strings(args);
+
+            if args.len() >= 2 {
+                st.borrow_mut()
+                    .effects
+                    .aliases
+                    .push((args[0].clone(), args[1].clone()));
+            }
+            Ok(())
+        })?,
+    )?;
+
+    for name in &["hide_version", "hide_modulefile", "hide", "forbid"] {
+        let st = state.clone();
+
+        globals.set(
+            *name,
+            lua.create_function(move |_, args: MultiValue| {
+                let args: Vec<Value> = args.into_iter().collect();
+
+                /* hide{name="foo/1.0", ...} or hide_version("foo/1.0") */
+                let name = match args.first() {
+                    Some(Value::Table(t)) => {
+                        t.get::<_, Value>("name").ok().and_then(|v| string(&v))
+                    }
+                    Some(v) => string(v),
+                    None => None,
+                };
+
+                if let Some(name) = name {
+                    st.borrow_mut().effects.hidden.push(name);
+                }
+                Ok(())
+            })?,
+        )?;
+    }
+
+    let st = state.clone();
+    globals.set(
+        "conflict",
+        lua.create_function(move |_, args: MultiValue| {
+            st.borrow_mut().effects.conflicts.extend(strings(args));
+            Ok(())
+        })?,
+    )?;
+
+    let st = state.clone();
+    globals.set(
+        "family",
+        lua.create_function(move |_, args: MultiValue| {
+            st.borrow_mut().effects.families.extend(strings(args));
+            Ok(())
+        })?,
+    )?;
+
+    for name in CONSTRAINT_FUNCTIONS {
+        globals.set(
+            *name,
+            lua.create_function(|_, (name, _): (String, MultiValue)| Ok(name))?,
+        )?;
+    }
+
+    for (name, prepend) in &[("prepend_path", true), ("append_path", false)] {
+        let st = state.clone();
+        let prepend = *prepend;
+
+        globals.set(
+            *name,
+            lua.create_function(move |_, args: MultiValue| {
+                if let Some((var, value, delim)) = path_args(args) {
+                    st.borrow_mut().modify_path(prepend, var, &value, &delim);
+                }
+                Ok(())
+            })?,
+        )?;
+    }
+
+    for name in &["setenv", "pushenv"] {
+        let st = state.clone();
+
+        globals.set(
+            *name,
+            lua.create_function(move |_, args: MultiValue| {
+                let args = strings(args);
+
+                if let Some(var) = args.first() {
+                    let value = args.get(1).cloned().unwrap_or_default();
+                    let mut st = st.borrow_mut();
+
+                    st.env.insert(var.clone(), value.clone());
+                    st.effects.env.push(EnvOp::Set(var.clone(), value));
+                }
+                Ok(())
+            })?,
+        )?;
+    }
+
+    let st = state.clone();
+    globals.set(
+        "unsetenv",
+        lua.create_function(move |_, args: MultiValue| {
+            if let Some(var) = strings(args).into_iter().next() {
+                let mut st = st.borrow_mut();
+
+                st.env.remove(&var);
+                st.effects.env.push(EnvOp::Unset(var));
+            }
+            Ok(())
+        })?,
+    )?;
+
+    for name in &["LmodError", "LmodBreak"] {
+        let name = *name;
+
+        globals.set(
+            name,
+            lua.create_function(move |_, args: Variadic<String>| -> mlua::Result<()> {
+                Err(mlua::Error::RuntimeError(format!(
+                    "{}: {}",
+                    name,
+                    args.concat()
+                )))
+            })?,
+        )?;
+    }
+
+    /* introspection */
+
+    let code = file.code.clone();
+    let (mod_name, mod_version) = match code.rfind('/') {
+        Some(i) => (code[..i].to_string(), code[i + 1..].to_string()),
+        None => (code.clone(), String::new()),
+    };
+
+    constant(lua, globals, "myModuleName", mod_name)?;
+    constant(lua, globals, "myModuleVersion", mod_version)?;
+    constant(lua, globals, "myModuleFullName", code.clone())?;
+    constant(lua, globals, "myModuleUsrName", code.clone())?;
+    constant(
+        lua,
+        globals,
+        "myFileName",
+        file.path.to_string_lossy().to_string(),
+    )?;
+    constant(lua, globals, "myShellName", "bash".to_string())?;
+    constant(lua, globals, "myShellType", "sh".to_string())?;
+    constant(lua, globals, "mode", "load".to_string())?;
+    constant(lua, globals, "LmodVersion", "8.7".to_string())?;
+    constant(lua, globals, "capture", String::new())?;
+    constant(lua, globals, "subprocess", String::new())?;
+
+    let path = file.path.clone();
+    globals.set(
+        "hierarchyA",
+        lua.create_function(move |_, (full_name, levels): (String, usize)| {
+            Ok(hierarchy(&path, &full_name, levels))
+        })?,
+    )?;
+
+    globals.set(
+        "pathJoin",
+        lua.create_function(|_, parts: Variadic<Value>| {
+            let parts: Vec<String> = strings(parts.into_iter().collect());
+            Ok(path_join(&parts))
+        })?,
+    )?;
+
+    globals.set(
+        "isFile",
+        lua.create_function(|_, p: String| Ok(Path::new(&p).is_file()))?,
+    )?;
+
+    globals.set(
+        "isDir",
+        lua.create_function(|_, p: String| Ok(Path::new(&p).is_dir()))?,
+    )?;
+
+    globals.set(
+        "convertToCanonical",
+        lua.create_function(|_, v: String| Ok(v))?,
+    )?;
+
+    Ok(())
+}
+
+impl State {
+    fn modify_path(&mut self, prepend: bool, var: String, value: &str, delim: &str) {
+        for part in value.split(delim).filter(|x| !x.is_empty()) {
+            let current = self.env.get(&var).cloned().unwrap_or_default();
+            let updated = if current.is_empty() {
+                part.to_string()
+            } else if prepend {
+                format!("{}{}{}", part, delim, current)
+            } else {
+                format!("{}{}{}", current, delim, part)
+            };
+
+            self.env.insert(var.clone(), updated);
+            self.effects.env.push(if prepend {
+                EnvOp::Prepend(var.clone(), part.to_string())
+            } else {
+                EnvOp::Append(var.clone(), part.to_string())
+            });
+        }
+    }
+}
+
+fn constant(lua: &Lua, globals: &Table, name: &str, value: String) -> mlua::Result<()> {
+    globals.set(
+        name,
+        lua.create_function(move |_, _: MultiValue| Ok(value.clone()))?,
+    )
+}
+
+/* strings() converts lua arguments to strings, skipping non-scalar values */
+fn strings(args: MultiValue) -> Vec<String> {
+    args.into_iter().filter_map(|v| string(&v)).collect()
+}
+
+fn string(v: &Value) -> Option<String> {
+    match v {
+        Value::String(s) => s.to_str().ok().map(|x| x.to_string()),
+        Value::Integer(i) => Some(i.to_string()),
+        Value::Number(n) => Some(n.to_string()),
+        _ => None,
+    }
+}
+
+/*
+ * path_args() handles both call styles:
+ *   prepend_path("PATH", "/x/bin", ":")
+ *   prepend_path{"PATH", "/x/bin", delim=":", priority=100}
+ */
+
+fn path_args(args: MultiValue) -> Option<(String, String, String)> {
+    let args: Vec<Value> = args.into_iter().collect();
+
+    if let Some(Value::Table(t)) = args.first() {
+        let var = string(&t.get::<_, Value>(1).ok()?)?;
+        let value = string(&t.get::<_, Value>(2).ok()?)?;
+        let delim = t
+            .get::<_, Value>("delim")
+            .ok()
+            .and_then(|x| string(&x))
+            .unwrap_or_else(|| ":".to_string());
+
+        return Some((var, value, delim));
+    }
+
+    let var = string(args.first()?)?;
+    let value = string(args.get(1)?)?;
+    let delim = args
+        .get(2)
+        .and_then(string)
+        .unwrap_or_else(|| ":".to_string());
+
+    Some((var, value, delim))
+}
+
+fn path_join(parts: &[String]) -> String {
+    let joined = parts
+        .iter()
+        .filter(|x| !x.is_empty())
+        .cloned()
+        .collect::<Vec<String>>()
+        .join("/");
+
+    let mut out = String::new();
+
+    for c in joined.chars() {
+        if !(c == '/' && out.ends_with('/')) {
+            out.push(c);
+        }
+    }
+
+    out
+}
+
+/*
+ * hierarchy() mirrors lmod's hierarchyA(): it walks up the modulefile path
+ * above the module's own name, returning one "name/version" entry per level
+ */
+
+fn hierarchy(path: &Path, full_name: &str, levels: usize) -> Vec<String> {
+    let width = full_name.split('/').count();
+    let dirs: Vec<String> = path
+        .parent()
+        .map(|p| p.iter().map(|x| x.to_string_lossy().to_string()).collect())
+        .unwrap_or_default();
+
+    /* the modulefile itself supplies the last component of the full name */
+    let mut end = dirs.len().saturating_sub(width - 1);
+    let mut out = Vec::new();
+
+    for _ in 0..levels {
+        if end < width {
+            break;
+        }
+
+        out.push(dirs[end - width..end].join("/"));
+        end -= width;
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crawl::ModuleType;
+    use std::path::PathBuf;
+
+    fn run(contents: &str) -> Effects {
+        run_at("/mods/foo/1.0.lua", "foo/1.0", contents)
+    }
+
+    fn run_at(path: &str, code: &str, contents: &str) -> Effects {
+        let file = crawl::ModuleFile {
+            path: PathBuf::from(path),
+            code: code.to_string(),
+            modtype: ModuleType::LMOD,
+            hash: None,
+            chain: Vec::new(),
+            default: false,
+            hidden: false,
+            aliases: Vec::new(),
+            activate: None,
+        };
+
+        eval(contents, &file)
+    }
+
+    #[test]
+    fn shebang() {
+        let contents = "#!/usr/bin/env lua\nprepend_path(\"PATH\", \"/opt/foo/bin\")\n";
+
+        assert!(is_lua(contents));
+        assert_eq!(run(contents).paths("PATH"), vec!["/opt/foo/bin"]);
+    }
+
+    #[test]
+    fn path_join() {
+        let effects = run(
+            "prepend_path(\"PATH\", pathJoin(\"/opt/\", \"\", \"foo\", myModuleVersion(), \"bin\"))",
+        );
+
+        assert_eq!(effects.paths("PATH"), vec!["/opt/foo/1.0/bin"]);
+    }
+
+    #[test]
+    fn path_args() {
+        let effects = run(
+            "prepend_path{\"PATH\", \"/opt/foo/bin\", priority=100}\nappend_path(\"PATH\", \"/opt/foo/sbin\")\nprepend_path{\"LIST\", \"/a;/b\", delim=\";\"}",
+        );
+
+        assert_eq!(effects.paths("PATH"), vec!["/opt/foo/bin", "/opt/foo/sbin"]);
+        assert_eq!(effects.paths("LIST"), vec!["/a", "/b"]);
+    }
+
+    #[test]
+    fn pushenv_getenv() {
+        let effects = run(
+            "pushenv(\"FOO_ROOT\", \"/opt/foo\")\nprepend_path(\"PATH\", os.getenv(\"FOO_ROOT\") .. \"/bin\")\nif os.getenv(\"MII_UNSET_TEST_VAR\") == nil then prepend_path(\"PATH\", \"/unset/bin\") end",
+        );
+
+        assert_eq!(effects.paths("FOO_ROOT"), vec!["/opt/foo"]);
+        assert_eq!(effects.paths("PATH"), vec!["/opt/foo/bin", "/unset/bin"]);
+    }
+
+    #[test]
+    fn hierarchy_levels() {
+        let path = Path::new("/apps/mpi/gcc/9.3/openmpi/4.0/fftw/3.3.lua");
+
+        assert_eq!(
+            hierarchy(path, "fftw/3.3", 2),
+            vec!["openmpi/4.0", "gcc/9.3"]
+        );
+        assert_eq!(hierarchy(path, "fftw/3.3", 5).len(), 3);
+
+        let effects = run_at(
+            "/apps/gcc/9.3/fftw/3.3.lua",
+            "fftw/3.3",
+            "local compiler = hierarchyA(myModuleFullName(), 1)[1]\nprepend_path(\"MODULEPATH\", pathJoin(\"/apps/mods\", compiler))",
+        );
+
+        assert_eq!(effects.paths("MODULEPATH"), vec!["/apps/mods/gcc/9.3"]);
+    }
+
+    #[test]
+    fn sandbox() {
+        let effects = run(
+            "if io == nil and os.execute == nil then prepend_path(\"PATH\", \"/sandboxed/bin\") end",
+        );
+
+        assert_eq!(effects.paths("PATH"), vec!["/sandboxed/bin"]);
+
+        let effects = run(
+            "prepend_path(\"PATH\", \"/before/bin\")\nio.open(\"/etc/passwd\")\nprepend_path(\"PATH\", \"/never/bin\")",
+        );
+
+        assert_eq!(effects.paths("PATH"), vec!["/before/bin"]);
+
+        let effects = run(
+            "prepend_path(\"PATH\", \"/before/bin\")\nos.execute(\"true\")\nprepend_path(\"PATH\", \"/never/bin\")",
+        );
+
+        assert_eq!(effects.paths("PATH"), vec!["/before/bin"]);
+    }
+
+    #[test]
+    fn instruction_limit() {
+        let effects = run(
+            "prepend_path(\"PATH\", \"/before/bin\")\nwhile true do end\nprepend_path(\"PATH\", \"/never/bin\")",
+        );
+
+        assert_eq!(effects.paths("PATH"), vec!["/before/bin"]);
+    }
+}
@@ -1,14 +1,23 @@
 #[macro_use]
 extern crate clap;
 #[macro_use]
-extern crate lazy_static;
-#[macro_use]
 extern crate log;
 
 mod analysis;
 mod crawl;
 mod db;
+mod deps;
+mod easybuild;
+mod elf;
 mod engine;
+mod envs;
+mod lmod;
+mod modulerc;
+mod prefixes;
+mod source;
+mod spack;
+mod spider;
+mod tcl;
 
 use clap::AppSettings;
 use std::env;
@@ -25,9 +34,14 @@ fn main() {
         (@arg datadir: -s --datadir +takes_value "Override data directory")
         (@subcommand sync =>
             (about: "Synchronize module index")
+            (@arg cache: -c --cache +takes_value "Seed from an Lmod spider cache (spiderT.lua)")
         )
         (@subcommand build =>
             (about: "Rebuild module index")
+            (@arg cache: -c --cache +takes_value "Seed from an Lmod spider cache (spiderT.lua)")
+        )
+        (@subcommand skipped =>
+            (about: "List files in module trees which were not indexed")
         )
         (@subcommand exact =>
             (about: "Search for an exact command")
@@ -37,6 +51,53 @@ fn main() {
             (about: "Search for similar commands")
             (@arg command: +required "Command hint")
         )
+        (@subcommand man =>
+            (about: "Search for a man or info page")
+            (@arg page: +required "Page to search")
+        )
+        (@subcommand lib =>
+            (about: "Search for a shared library, any version")
+            (@arg library: +required "Library name, e.g. libhdf5.so.103")
+        )
+        (@subcommand py =>
+            (about: "Search for a python package")
+            (@arg package: +required "Package to import")
+        )
+        (@subcommand pkgconfig =>
+            (about: "Search for a pkg-config package")
+            (@arg package: +required "Package name, as passed to pkg-config")
+        )
+        (@subcommand cmake =>
+            (about: "Search for a CMake package config")
+            (@arg package: +required "Package name, as passed to find_package()")
+        )
+        (@subcommand header =>
+            (about: "Search for a header file")
+            (@arg header: +required "Header as written in an #include, e.g. hdf5/H5Cpp.h")
+        )
+        (@subcommand setenv =>
+            (about: "Search for modules setting an environment variable")
+            (@arg variable: +required "Variable name, e.g. CUDA_HOME")
+        )
+        (@subcommand fact =>
+            (about: "Search the facts of any analyzer")
+            (@arg analyzer: +required "Analyzer name, e.g. bins or envs")
+            (@arg value: +required "Value of the analyzer's first field")
+        )
+        (@subcommand deps =>
+            (about: "Show module dependencies")
+            (@arg module: "Module to inspect, all modules if omitted")
+            (@arg reverse: -r --reverse "Show the modules depending on it instead")
+            (@arg dot: --dot "Print a graphviz dot graph")
+        )
+        (@subcommand spider =>
+            (about: "Show how to load a module, including hierarchy prerequisites")
+            (@arg module: +required "Module name or code")
+        )
+        (@subcommand info =>
+            (about: "Show what a module provides")
+            (@arg module: +required "Module name or code")
+        )
     )
     .get_matches();
 
@@ -60,35 +121,150 @@ fn main() {
 
     let datadir = Path::new(&datadir);
 
-    if let Err(e) = DirBuilder::new().recursive(true).create(&datadir) {
+    if let Err(e) = DirBuilder::new().recursive(true).create(datadir) {
         panic!(
             "Failed to initialize data directory in {} : {}",
             datadir.display(),
-            e.to_string()
+            e
         );
     }
 
+    /* package manager metadata. site analyzers are registered the same way */
+    let mut analyzers = analysis::Registry::builtin();
+    let extra: Vec<Box<dyn analysis::Analyzer>> = vec![
+        Box::new(spack::Spack::default()),
+        Box::new(easybuild::EasyBuild),
+    ];
+
+    for a in extra {
+        if let Err(e) = analyzers.register(a) {
+            warn!("Ignoring analyzer: {}", e);
+        }
+    }
+
     let mut ctrl = engine::Engine::new(
-        env::var("MODULEPATH").unwrap_or(String::new()),
+        env::var("MODULEPATH").unwrap_or_default(),
         datadir.join("index.db"),
+        analyzers,
     );
 
-    if let Some(_) = matches.subcommand_matches("sync") {
+    /* conda environments and virtualenvs */
+    if let Ok(envpath) = env::var("MII_ENVPATH") {
+        ctrl.add_source(Box::new(envs::Envs::new(envpath)));
+    }
+
+    /* plain install prefixes without modulefiles */
+    if let Ok(prefixpath) = env::var("MII_PREFIXPATH") {
+        ctrl.add_source(Box::new(prefixes::Prefixes::new(prefixpath)));
+    }
+
+    /* lmod spider cache seeding the index, --cache takes precedence */
+    for cmd in &["sync", "build"] {
+        if let Some(m) = matches.subcommand_matches(cmd) {
+            if let Some(cache) = m
+                .value_of("cache")
+                .map(|x| x.to_string())
+                .or_else(|| env::var("MII_SPIDER_CACHE").ok())
+                .filter(|x| !x.is_empty())
+            {
+                ctrl.use_spider_cache(Path::new(&cache));
+            }
+        }
+    }
+
+    if matches.subcommand_matches("sync").is_some() {
         ctrl.sync_light();
     }
 
-    if let Some(_) = matches.subcommand_matches("build") {
+    if matches.subcommand_matches("build").is_some() {
         ctrl.destroy_db();
         println!("[mii] Rebuilding index..");
         ctrl.sync_light();
     }
 
+    if matches.subcommand_matches("skipped").is_some() {
+        println!("[");
+        for (path, reason) in ctrl.skipped() {
+            println!("    {{{}:{}}},", json(&path), json(&reason));
+        }
+        println!("]");
+    }
+
     if let Some(matches) = matches.subcommand_matches("exact") {
         let res = ctrl.search_bin_exact(matches.value_of("command").unwrap().to_string());
 
+        /*
+         * candidates which would conflict with the loaded modules go last.
+         * among the rest, the site default version of a module goes first.
+         */
+        let loaded: Vec<String> = env::var("LOADEDMODULES")
+            .unwrap_or_default()
+            .split(':')
+            .filter(|x| !x.is_empty())
+            .map(|x| x.to_string())
+            .collect();
+
+        let mut res: Vec<(db::BinResult, Vec<String>)> = res
+            .into_iter()
+            .map(|r| {
+                let conflicts = ctrl.find_conflicts(&r.code, &loaded);
+                (r, conflicts)
+            })
+            .collect();
+
+        res.sort_by_key(|(r, conflicts)| (!conflicts.is_empty(), !r.default));
+
         println!("[");
-        for r in res {
-            println!("    {{\"{}\":\"{}\"}},", r.code, r.command);
+        for (r, conflicts) in res {
+            let mut fields = format!("{}:{}", json(&r.code), json(&r.command));
+
+            /* spack installs show their full spec rather than the hash */
+            if let Some(spec) = ctrl.module_spec(&r.code) {
+                fields.push_str(&format!(",\"spec\":{}", json(&spec)));
+            }
+
+            if let Some(toolchain) = ctrl.module_toolchain(&r.code) {
+                fields.push_str(&format!(",\"toolchain\":{}", json(&toolchain)));
+            }
+
+            if !conflicts.is_empty() {
+                fields.push_str(&format!(",\"conflicts\":{}", json(&conflicts.join(" "))));
+            }
+
+            /* libraries the command needs from other modules */
+            let requires = ctrl.find_requires(&r);
+
+            if !requires.is_empty() {
+                let codes: Vec<&str> = requires.iter().map(|x| x.code.as_str()).collect();
+                fields.push_str(&format!(",\"requires\":{}", json(&codes.join(" "))));
+            }
+
+            fields.push_str(&provider_fields(&r));
+
+            /*
+             * hierarchical modules need their whole chain loaded, in order. the
+             * chains of required modules go first, then the modules themselves.
+             */
+            if !r.chain.is_empty() || !requires.is_empty() {
+                let mut load: Vec<&str> = Vec::new();
+
+                for m in requires
+                    .iter()
+                    .flat_map(|x| &x.chain)
+                    .chain(&r.chain)
+                    .map(|x| x.as_str())
+                    .chain(requires.iter().map(|x| x.code.as_str()))
+                    .chain(std::iter::once(r.code.as_str()))
+                {
+                    if !load.contains(&m) {
+                        load.push(m);
+                    }
+                }
+
+                fields.push_str(&format!(",\"load\":{}", json(&load.join(" "))));
+            }
+
+            println!("    {{{}}},", fields);
         }
         println!("]");
     }
@@ -98,8 +274,231 @@ fn main() {
 
         println!("[");
         for r in res {
-            println!("    {{\"{}\":\"{}\"}},", r.code, r.command);
+            println!(
+                "    {{{}:{}{}}},",
+                json(&r.code),
+                json(&r.command),
+                provider_fields(&r)
+            );
+        }
+        println!("]");
+    }
+
+    if let Some(matches) = matches.subcommand_matches("man") {
+        let res = ctrl.search_man(matches.value_of("page").unwrap().to_string());
+
+        println!("[");
+        for r in res {
+            println!("    {{{}:{}}},", json(&r.code), json(&r.page));
+        }
+        println!("]");
+    }
+
+    if let Some(matches) = matches.subcommand_matches("lib") {
+        let res = ctrl.search_lib(matches.value_of("library").unwrap().to_string());
+
+        println!("[");
+        for r in res {
+            println!("    {{{}:{}}},", json(&r.code), json(&r.lib));
+        }
+        println!("]");
+    }
+
+    if let Some(matches) = matches.subcommand_matches("py") {
+        let res = ctrl.search_py(matches.value_of("package").unwrap().to_string());
+
+        println!("[");
+        for r in res {
+            println!("    {{{}:{}}},", json(&r.code), json(&r.package));
+        }
+        println!("]");
+    }
+
+    if let Some(matches) = matches.subcommand_matches("pkgconfig") {
+        let res = ctrl.search_pkgconfig(matches.value_of("package").unwrap().to_string());
+
+        println!("[");
+        for r in res {
+            let desc = format!("{} {}", r.name, r.version);
+            println!("    {{{}:{}}},", json(&r.code), json(desc.trim_end()));
+        }
+        println!("]");
+    }
+
+    if let Some(matches) = matches.subcommand_matches("cmake") {
+        let res = ctrl.search_cmake(matches.value_of("package").unwrap().to_string());
+
+        println!("[");
+        for r in res {
+            println!("    {{{}:{}}},", json(&r.code), json(&r.package));
+        }
+        println!("]");
+    }
+
+    if let Some(matches) = matches.subcommand_matches("header") {
+        let res = ctrl.search_header(matches.value_of("header").unwrap().to_string());
+
+        println!("[");
+        for r in res {
+            println!("    {{{}:{}}},", json(&r.code), json(&r.header));
+        }
+        println!("]");
+    }
+
+    if let Some(matches) = matches.subcommand_matches("setenv") {
+        let res = ctrl.search_env(matches.value_of("variable").unwrap().to_string());
+
+        println!("[");
+        for r in res {
+            println!(
+                "    {{{}:{},\"op\":{}}},",
+                json(&r.code),
+                json(&r.value),
+                json(&r.op)
+            );
+        }
+        println!("]");
+    }
+
+    if let Some(matches) = matches.subcommand_matches("fact") {
+        let analyzer = matches.value_of("analyzer").unwrap();
+
+        match ctrl.search_fact(analyzer, matches.value_of("value").unwrap()) {
+            Some(res) => {
+                println!("[");
+                for (code, fields) in res {
+                    println!("    {{{}:{}}},", json(&code), json(&fields.join(" ")));
+                }
+                println!("]");
+            }
+            None => eprintln!("[mii] no analyzer named \"{}\"", analyzer),
+        }
+    }
+
+    if let Some(matches) = matches.subcommand_matches("deps") {
+        let graph = ctrl.deps_graph();
+        let reverse = matches.is_present("reverse");
+
+        let roots = match matches.value_of("module") {
+            Some(m) => graph.find(m),
+            None => graph.modules(),
+        };
+
+        if roots.is_empty() {
+            match matches.value_of("module") {
+                Some(m) => eprintln!("[mii] no indexed modules match \"{}\"", m),
+                None => eprintln!("[mii] index is empty"),
+            }
+        } else if matches.is_present("dot") {
+            print!("{}", graph.dot(&roots, reverse));
+        } else {
+            for root in roots {
+                print!("{}", graph.tree(&root, reverse));
+            }
+        }
+    }
+
+    if let Some(matches) = matches.subcommand_matches("spider") {
+        let res = ctrl.module_info(matches.value_of("module").unwrap());
+
+        println!("[");
+        for m in res {
+            let mut load = m.chain.clone();
+            load.push(m.code.clone());
+
+            println!("    {{{}:{}}},", json(&m.code), json(&load.join(" ")));
         }
         println!("]");
     }
+
+    if let Some(matches) = matches.subcommand_matches("info") {
+        let module = matches.value_of("module").unwrap();
+        let res = ctrl.module_info(module);
+
+        if res.is_empty() {
+            eprintln!("[mii] no indexed modules match \"{}\"", module);
+        }
+
+        for (i, m) in res.iter().enumerate() {
+            if i > 0 {
+                println!();
+            }
+
+            if m.default {
+                println!("{} (default)", m.code);
+            } else {
+                println!("{}", m.code);
+            }
+
+            for (label, value) in &[
+                ("description", &m.description),
+                ("spec", &m.spec),
+                ("toolchain", &m.toolchain),
+                ("category", &m.category),
+                ("homepage", &m.homepage),
+                ("keywords", &m.keywords),
+            ] {
+                if !value.is_empty() {
+                    println!("  {:<12} {}", label, value);
+                }
+            }
+
+            if !m.bins.is_empty() {
+                println!("  {:<12} {}", "commands", m.bins.join(" "));
+            }
+
+            if !m.aliases.is_empty() {
+                println!("  {:<12} {}", "aliases", m.aliases.join(" "));
+            }
+
+            if !m.dependencies.is_empty() {
+                println!("  {:<12} {}", "dependencies", m.dependencies.join(" "));
+            }
+
+            if !m.chain.is_empty() {
+                println!("  {:<12} {} {}", "load", m.chain.join(" "), m.code);
+            }
+
+            if m.activate.is_empty() {
+                println!("  {:<12} {}", "modulefile", m.path);
+            } else {
+                println!("  {:<12} {}", "kind", m.kind);
+                println!("  {:<12} {}", "activate", m.activate);
+                println!("  {:<12} {}", "path", m.path);
+            }
+
+            if !m.help.is_empty() {
+                println!();
+
+                for line in m.help.lines() {
+                    println!("  {}", line);
+                }
+            }
+        }
+    }
+}
+
+/* json() renders a value as a json string, for the pseudo-json the shell hooks parse */
+fn json(s: &str) -> String {
+    serde_json::to_string(s).unwrap()
+}
+
+/* provider_fields() renders how to activate providers which aren't modulefiles */
+fn provider_fields(r: &db::BinResult) -> String {
+    let mut fields = String::new();
+
+    if !r.activate.is_empty() {
+        fields.push_str(&format!(
+            ",\"kind\":{},\"activate\":{}",
+            json(&r.kind),
+            json(&r.activate)
+        ));
+    }
+
+    /* prefixes are put in PATH by the shell hooks directly */
+    if r.kind == prefixes::PREFIX {
+        fields.push_str(&format!(",\"bin\":{}", json(&format!("{}/bin", r.path))));
+    }
+
+    fields
 }
@@ -0,0 +1,214 @@
+/*
+ * modulerc.rs
+ *
+ * .version, .modulerc and .modulerc.lua handling
+ *
+ * rc files sit next to the modulefiles (or at the top of a modulepath) and
+ * decide which version is the default, which names are aliases and which
+ * modules are hidden from users. they're evaluated with the same sandboxed
+ * interpreters as modulefiles, but only the rc directives are kept.
+ */
+
+use crate::analysis::Effects;
+use crate::crawl::{ModuleFile, ModuleType};
+use crate::deps;
+use crate::lmod;
+use crate::tcl;
+
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/* rc files looked for in every module directory */
+const RC_FILES: &[(&str, ModuleType)] = &[
+    (".version", ModuleType::TCL),
+    (".modulerc", ModuleType::TCL),
+    (".modulerc.lua", ModuleType::LMOD),
+];
+
+/* aliases can point at other aliases; give up on chains longer than this */
+const MAX_ALIAS_DEPTH: usize = 8;
+
+#[derive(Default)]
+struct Directives {
+    defaults: Vec<String>,
+    aliases: Vec<(String, String)>,
+    hidden: Vec<String>,
+}
+
+/*
+ * apply() evaluates the rc files around crawled modulefiles and marks the
+ * defaults, aliases and hidden modules among them
+ */
+
+pub fn apply(files: &mut [ModuleFile]) {
+    let mut rc = Directives::default();
+
+    for (dir, prefix) in module_dirs(files) {
+        for (name, modtype) in RC_FILES {
+            let path = dir.join(name);
+
+            if let Ok(contents) = fs::read_to_string(&path) {
+                debug!("Reading {}", path.display());
+                rc.add(&prefix, rc_eval(&contents, path, prefix.clone(), modtype));
+            }
+        }
+
+        /* lmod also takes a "default" symlink to the default modulefile */
+        if let Ok(target) = fs::read_link(dir.join("default")) {
+            if let Some(stem) = target.file_name().and_then(|x| x.to_str()) {
+                rc.defaults.push(qualify(
+                    &prefix,
+                    &format!("/{}", stem.trim_end_matches(".lua")),
+                ));
+            }
+        }
+    }
+
+    let codes: Vec<String> = files.iter().map(|f| f.code.clone()).collect();
+
+    let defaults: Vec<String> = rc
+        .defaults
+        .iter()
+        .filter_map(|x| rc.resolve(x, &codes, &[]))
+        .collect();
+
+    let aliases: Vec<(String, String)> = rc
+        .aliases
+        .iter()
+        .filter_map(|(alias, target)| {
+            rc.resolve(target, &codes, &defaults)
+                .map(|code| (alias.clone(), code))
+        })
+        .collect();
+
+    for f in files.iter_mut() {
+        f.default = defaults.contains(&f.code);
+        f.hidden = rc
+            .hidden
+            .iter()
+            .any(|h| deps::matches(h, &f.code) || Path::new(h) == f.path);
+        f.aliases = aliases
+            .iter()
+            .filter(|(_, code)| *code == f.code)
+            .map(|(alias, _)| alias.clone())
+            .collect();
+    }
+}
+
+impl Directives {
+    /* add() keeps the rc directives of one file, with names made absolute */
+    fn add(&mut self, prefix: &str, effects: Effects) {
+        for (module, name) in effects.versions {
+            let module = qualify(prefix, &module);
+
+            if name == "default" {
+                self.defaults.push(module);
+            } else {
+                /* any other symbolic version is an alias: foo/stable -> foo/1.0 */
+                let alias = if name.contains('/') {
+                    qualify(prefix, &name)
+                } else {
+                    match module.rfind('/') {
+                        Some(i) => format!("{}/{}", &module[..i], name),
+                        None => name,
+                    }
+                };
+
+                self.aliases.push((alias, module));
+            }
+        }
+
+        for (alias, target) in effects.aliases {
+            self.aliases
+                .push((qualify(prefix, &alias), qualify(prefix, &target)));
+        }
+
+        self.hidden
+            .extend(effects.hidden.iter().map(|x| qualify(prefix, x)));
+    }
+
+    /*
+     * resolve() maps a name from an rc file to a module code, following
+     * aliases. bare names pick the default version if there is one, otherwise
+     * the highest.
+     */
+
+    fn resolve(&self, name: &str, codes: &[String], defaults: &[String]) -> Option<String> {
+        let mut name = name.to_string();
+
+        for _ in 0..MAX_ALIAS_DEPTH {
+            if codes.contains(&name) {
+                return Some(name);
+            }
+
+            match self.aliases.iter().find(|(alias, _)| *alias == name) {
+                Some((_, target)) => name = target.clone(),
+                None => break,
+            }
+        }
+
+        deps::resolve(&name, codes, defaults)
+    }
+}
+
+/*
+ * qualify() makes a name from an rc file absolute. names starting with '/'
+ * are relative to the directory the rc file is in: "/1.0" in foo/ is foo/1.0
+ */
+
+fn qualify(prefix: &str, name: &str) -> String {
+    if name.starts_with('/') && !prefix.is_empty() {
+        format!("{}{}", prefix, name)
+    } else {
+        name.trim_start_matches('/').to_string()
+    }
+}
+
+/*
+ * module_dirs() lists every directory between the crawled modulefiles and
+ * their modulepath root, along with the module name prefix it stands for
+ */
+
+fn module_dirs(files: &[ModuleFile]) -> BTreeSet<(PathBuf, String)> {
+    let mut out = BTreeSet::new();
+
+    for f in files {
+        let depth = Path::new(&f.code).components().count();
+        let root = match f.path.ancestors().nth(depth) {
+            Some(root) => root,
+            None => continue,
+        };
+
+        for dir in f.path.ancestors().skip(1).take(depth) {
+            let prefix = dir
+                .strip_prefix(root)
+                .unwrap()
+                .to_string_lossy()
+                .to_string();
+            out.insert((dir.to_path_buf(), prefix));
+        }
+    }
+
+    out
+}
+
+fn rc_eval(contents: &str, path: PathBuf, code: String, modtype: &ModuleType) -> Effects {
+    let file = ModuleFile {
+        path,
+        code,
+        modtype: modtype.clone(),
+        hash: None,
+        chain: Vec::new(),
+        default: false,
+        hidden: false,
+        aliases: Vec::new(),
+        activate: None,
+    };
+
+    match modtype {
+        ModuleType::LMOD => lmod::eval(contents, &file),
+        ModuleType::TCL => tcl::eval(contents, &file),
+        ModuleType::PROVIDER(_) => Effects::default(),
+    }
+}
@@ -0,0 +1,116 @@
+/*
+ * prefixes.rs
+ *
+ * virtual module roots
+ *
+ * software is often installed straight into a prefix with no modulefile. the
+ * roots in MII_PREFIXPATH are searched for prefixes with a bin/ directory,
+ * which are indexed as synthetic modules named after their path:
+ *
+ *   /opt/matlab/R2023a/bin   prefix:matlab/R2023a
+ *   /opt/ripgrep/bin         prefix:ripgrep
+ *
+ * activating one just puts its bin/ directory first in PATH. the shell hooks
+ * do that themselves, the activation command is only shown to the user.
+ */
+
+use crate::analysis::{Effects, EnvOp};
+use crate::crawl::{ModuleFile, ModuleType};
+use crate::deps;
+use crate::source::{self, Source};
+
+use std::path::Path;
+
+/* kind of provider */
+pub const PREFIX: &str = "prefix";
+
+/* Prefixes is the source of prefixes in a colon-separated list of roots */
+pub struct Prefixes {
+    roots: String,
+}
+
+impl Prefixes {
+    pub fn new(roots: String) -> Prefixes {
+        Prefixes { roots }
+    }
+}
+
+impl Source for Prefixes {
+    fn name(&self) -> &'static str {
+        "prefixes"
+    }
+
+    /* prefixes are looked for as <root>/<app>/bin and <root>/<app>/<version>/bin */
+    fn crawl(&self) -> Vec<ModuleFile> {
+        let mut out: Vec<ModuleFile> = Vec::new();
+
+        for root in self.roots.split(':').filter(|x| !x.is_empty()) {
+            for app in source::children(Path::new(root))
+                .into_iter()
+                .filter(|x| !hidden(x))
+            {
+                if let Some(p) = prefix(&app, root) {
+                    out.push(p);
+                    continue;
+                }
+
+                let mut versions: Vec<ModuleFile> = source::children(&app)
+                    .iter()
+                    .filter_map(|x| prefix(x, root))
+                    .collect();
+
+                /* as in a module tree without a set default, the highest version goes first */
+                if let Some(top) = versions
+                    .iter_mut()
+                    .max_by(|a, b| deps::version_cmp(&a.code, &b.code))
+                {
+                    top.default = true;
+                }
+
+                out.extend(versions);
+            }
+        }
+
+        out
+    }
+
+    fn effects(&self, prefix: &ModuleFile) -> Effects {
+        let mut effects = Effects::default();
+
+        effects.env.push(EnvOp::Prepend(
+            "PATH".to_string(),
+            prefix.path.join("bin").to_string_lossy().to_string(),
+        ));
+
+        effects
+    }
+}
+
+fn prefix(dir: &Path, root: &str) -> Option<ModuleFile> {
+    if hidden(dir) || !dir.join("bin").is_dir() {
+        return None;
+    }
+
+    let code = format!("{}:{}", PREFIX, dir.strip_prefix(root).ok()?.display());
+    let bin = dir.join("bin");
+
+    debug!("Found prefix {} in {}", code, dir.display());
+
+    Some(ModuleFile {
+        path: dir.to_path_buf(),
+        code,
+        modtype: ModuleType::PROVIDER(PREFIX),
+        hash: Some(source::hash(dir, Vec::new())),
+        chain: Vec::new(),
+        default: false,
+        hidden: false,
+        aliases: Vec::new(),
+        activate: Some(format!("export PATH={}:$PATH", source::quote(&bin))),
+    })
+}
+
+fn hidden(dir: &Path) -> bool {
+    dir.file_name()
+        .map(|x| x.to_string_lossy().starts_with('.'))
+        .unwrap_or(true)
+}
@@ -0,0 +1,75 @@
+/*
+ * source.rs
+ *
+ * providers which aren't modulefiles
+ *
+ * everything in the index is a provider, with a kind, an identifier (its
+ * code) and a way to activate it. modulefiles are found by crawling the
+ * MODULEPATH and are activated with `module load`. sources find any other
+ * kind: conda environments, plain install prefixes, container wrappers..
+ *
+ * providers from sources go through the same verify and analysis phases as
+ * modulefiles, so a changed hash is what gets one re-analyzed. they're
+ * offered by `mii exact` when no modulefile has the command.
+ */
+
+use crate::analysis::Effects;
+use crate::crawl::ModuleFile;
+
+use fasthash::xx;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub trait Source: Send + Sync {
+    /* name of the source, for logging */
+    fn name(&self) -> &'static str;
+
+    /*
+     * crawl() finds the providers. each one needs a ModuleType::PROVIDER
+     * naming its kind, a hash, and its activation command.
+     */
+
+    fn crawl(&self) -> Vec<ModuleFile>;
+
+    /* effects() describes what activating a provider does to the environment */
+    fn effects(&self, provider: &ModuleFile) -> Effects;
+}
+
+/* children() lists the entries of a directory, if it can be read */
+pub fn children(dir: &Path) -> Vec<PathBuf> {
+    match fs::read_dir(dir) {
+        Ok(entries) => entries.flatten().map(|x| x.path()).collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+/*
+ * quote() single-quotes a path for the activation commands, which the shell
+ * hooks eval. quotes inside it are closed, escaped and reopened.
+ */
+
+pub fn quote(path: &Path) -> String {
+    format!("'{}'", path.to_string_lossy().replace('\'', "'\\''"))
+}
+
+/*
+ * hash() fingerprints a prefix by the commands in its bin/ directory, along
+ * with any data the source has for telling versions of it apart
+ */
+
+pub fn hash(prefix: &Path, mut data: Vec<u8>) -> u32 {
+    let mut bins: Vec<String> = children(&prefix.join("bin"))
+        .iter()
+        .filter_map(|x| x.file_name())
+        .map(|x| x.to_string_lossy().to_string())
+        .collect();
+
+    bins.sort();
+
+    for b in bins {
+        data.extend(b.as_bytes());
+        data.push(b'\n');
+    }
+
+    xx::hash32(data)
+}
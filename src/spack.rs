@@ -0,0 +1,244 @@
+/*
+ * spack.rs
+ *
+ * spack install database enrichment
+ *
+ * spack generated modules only carry an opaque hash in their name
+ * (samtools/1.8-r54nmop). the install tree they point into has a database,
+ * <root>/.spack-db/index.json, with the full spec of every install. modules
+ * whose prefix is a spack install get that spec attached.
+ */
+
+use crate::analysis::{Analyzer, Fact, Module};
+
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+const SPACK_DB: &str = ".spack-db/index.json";
+
+/* compiler flag parameters, which are noise in a spec unless they're set */
+const FLAG_PARAMETERS: &[&str] = &[
+    "cflags", "cppflags", "cxxflags", "fflags", "ldflags", "ldlibs",
+];
+
+/* one install, with its spec rendered like `spack find` would */
+#[derive(Clone)]
+struct Install {
+    spec: String,
+    hash: String,
+    compiler: String,
+    variants: String,
+    dependencies: String,
+}
+
+type Installs = HashMap<PathBuf, Install>;
+
+/*
+ * Spack is the analyzer attaching install specs. databases are shared by
+ * thousands of modules, so each one is only parsed once.
+ */
+
+#[derive(Default)]
+pub struct Spack {
+    dbs: Mutex<HashMap<PathBuf, Option<Arc<Installs>>>>,
+}
+
+impl Analyzer for Spack {
+    fn name(&self) -> &'static str {
+        "spack"
+    }
+
+    fn fields(&self) -> &'static [&'static str] {
+        &[
+            "spec",
+            "hash",
+            "compiler",
+            "variants",
+            "dependencies",
+            "prefix",
+        ]
+    }
+
+    fn analyze(&self, module: &Module) -> Vec<Fact> {
+        let mut prefixes = module.effects.prefixes();
+        prefixes.extend(module.effects.paths("CMAKE_PREFIX_PATH"));
+
+        for prefix in prefixes {
+            let prefix = PathBuf::from(prefix.trim_end_matches('/'));
+
+            if let Some(install) = self.find(&prefix) {
+                return vec![vec![
+                    install.spec,
+                    install.hash,
+                    install.compiler,
+                    install.variants,
+                    install.dependencies,
+                    prefix.to_string_lossy().to_string(),
+                ]];
+            }
+        }
+
+        Vec::new()
+    }
+}
+
+impl Spack {
+    /* find() looks up the install at a prefix, in the database above it */
+    fn find(&self, prefix: &Path) -> Option<Install> {
+        let root = prefix
+            .ancestors()
+            .skip(1)
+            .find(|x| x.join(SPACK_DB).is_file())?;
+
+        let installs = self
+            .dbs
+            .lock()
+            .unwrap()
+            .entry(root.to_path_buf())
+            .or_insert_with(|| load(&root.join(SPACK_DB)).map(Arc::new))
+            .clone()?;
+
+        installs.get(prefix).cloned()
+    }
+}
+
+/* load() reads a spack database, keyed by install prefix */
+fn load(path: &Path) -> Option<Installs> {
+    let data = fs::read_to_string(path).ok()?;
+    let json: Value = match serde_json::from_str(&data) {
+        Ok(json) => json,
+        Err(e) => {
+            warn!("Failed to parse spack database {}: {}", path.display(), e);
+            return None;
+        }
+    };
+
+    let installs = json.pointer("/database/installs")?.as_object()?;
+    let mut out = HashMap::new();
+
+    for (hash, install) in installs {
+        let prefix = match install.get("path").and_then(Value::as_str) {
+            Some(prefix) => prefix.trim_end_matches('/'),
+            None => continue,
+        };
+
+        if let Some(spec) = install.get("spec") {
+            out.insert(PathBuf::from(prefix), describe(hash, spec, installs));
+        }
+    }
+
+    debug!("Loaded {} installs from {}", out.len(), path.display());
+    Some(out)
+}
+
+/* describe() renders a spec: samtools@1.8 %gcc@9.3.0 +htslib */
+fn describe(hash: &str, spec: &Value, installs: &Map<String, Value>) -> Install {
+    let text = |v: Option<&Value>| -> String {
+        match v {
+            Some(Value::String(s)) => s.clone(),
+            Some(Value::Null) | None => String::new(),
+            Some(v) => v.to_string(),
+        }
+    };
+
+    let name = text(spec.get("name"));
+    let version = text(spec.get("version"));
+
+    let compiler = match spec.get("compiler") {
+        Some(c) => format!("{}@{}", text(c.get("name")), text(c.get("version"))),
+        None => String::new(),
+    };
+
+    /* true variants and valued ones are shown, ~off variants only stored */
+    let mut shown: Vec<String> = Vec::new();
+    let mut variants: Vec<String> = Vec::new();
+
+    if let Some(params) = spec.get("parameters").and_then(Value::as_object) {
+        for (key, value) in params {
+            match value {
+                Value::Bool(true) => {
+                    shown.push(format!("+{}", key));
+                    variants.push(format!("+{}", key));
+                }
+                Value::Bool(false) => variants.push(format!("~{}", key)),
+                Value::Array(a) if a.is_empty() => (),
+                Value::Array(a) => {
+                    let joined: Vec<String> = a.iter().map(|x| text(Some(x))).collect();
+                    let v = format!("{}={}", key, joined.join(","));
+
+                    if !FLAG_PARAMETERS.contains(&key.as_str()) {
+                        shown.push(v.clone());
+                    }
+                    variants.push(v);
+                }
+                v => {
+                    let v = format!("{}={}", key, text(Some(v)));
+                    shown.push(v.clone());
+                    variants.push(v);
+                }
+            }
+        }
+    }
+
+    let mut parts = vec![format!("{}@{}", name, version)];
+
+    if !compiler.is_empty() {
+        parts.push(format!("%{}", compiler));
+    }
+
+    parts.extend(shown);
+
+    Install {
+        spec: parts.join(" "),
+        hash: hash.to_string(),
+        compiler,
+        variants: variants.join(" "),
+        dependencies: dependencies(spec, installs).join(" "),
+    }
+}
+
+/*
+ * dependencies() lists the direct dependencies of a spec as name@version.
+ * older databases keep them in a map by name, newer ones in a list.
+ */
+
+fn dependencies(spec: &Value, installs: &Map<String, Value>) -> Vec<String> {
+    let deps: Vec<(String, String)> = match spec.get("dependencies") {
+        Some(Value::Object(m)) => m
+            .iter()
+            .map(|(name, d)| (name.clone(), hash_of(d)))
+            .collect(),
+        Some(Value::Array(a)) => a
+            .iter()
+            .map(|d| {
+                let name = d.get("name").and_then(Value::as_str).unwrap_or("");
+                (name.to_string(), hash_of(d))
+            })
+            .collect(),
+        _ => Vec::new(),
+    };
+
+    deps.into_iter()
+        .map(|(name, hash)| {
+            match installs
+                .get(&hash)
+                .and_then(|x| x.pointer("/spec/version"))
+                .and_then(Value::as_str)
+            {
+                Some(version) => format!("{}@{}", name, version),
+                None => name,
+            }
+        })
+        .collect()
+}
+
+fn hash_of(dep: &Value) -> String {
+    ["hash", "build_hash"]
+        .iter()
+        .find_map(|k| dep.get(*k).and_then(Value::as_str))
+        .unwrap_or("")
+        .to_string()
+}
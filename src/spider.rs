@@ -0,0 +1,189 @@
+/*
+ * spider.rs
+ *
+ * lmod spider cache import
+ *
+ * sites with big module trees keep a spider cache (spiderT.lua, or moduleT.lua
+ * from older lmod releases) which already knows what every modulefile does to
+ * PATH. modules covered by the cache skip evaluation entirely; anything the
+ * cache doesn't know about, or which changed since it was written, is
+ * analyzed as usual.
+ *
+ * the cache is a lua file holding nested tables. module entries are found by
+ * shape rather than by position, since the layout differs between releases:
+ *
+ *   spiderT = { [mpath] = { [name] = { fileT = { [fullName] = {
+ *       fn = "/path/to/modulefile", pathA = { [dir] = 1 }, whatis = { .. }
+ *   } }, dirT = { .. } } } }
+ *   mpathMapT = { [mpath] = { [fullName] = parentMpath } }
+ */
+
+use crate::analysis::{Effects, EnvOp};
+use crate::crawl;
+
+use mlua::{Lua, LuaOptions, StdLib, Table, Value};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/* globals a cache file may define module entries in */
+const CACHE_TABLES: &[&str] = &["spiderT", "moduleT"];
+
+/* guards against cycles in hand-edited caches */
+const MAX_CACHE_DEPTH: usize = 16;
+
+#[derive(Default)]
+struct Entry {
+    paths: Vec<String>,
+    lpaths: Vec<String>,
+    whatis: Vec<String>,
+    help: String,
+}
+
+pub struct Cache {
+    entries: HashMap<PathBuf, Entry>,
+    modulepaths: HashMap<String, Vec<String>>, /* module code -> dirs it adds */
+    mtime: SystemTime,
+}
+
+impl Cache {
+    /* load() reads a spider cache file */
+    pub fn load(path: &Path) -> Result<Cache, String> {
+        let contents = fs::read_to_string(path).map_err(|e| e.to_string())?;
+        let mtime = fs::metadata(path)
+            .and_then(|x| x.modified())
+            .map_err(|e| e.to_string())?;
+
+        let lua = Lua::new_with(StdLib::NONE, LuaOptions::default()).map_err(|e| e.to_string())?;
+        lua.load(&contents).exec().map_err(|e| e.to_string())?;
+
+        let mut cache = Cache {
+            entries: HashMap::new(),
+            modulepaths: HashMap::new(),
+            mtime,
+        };
+
+        let globals = lua.globals();
+
+        for name in CACHE_TABLES {
+            if let Ok(Value::Table(t)) = globals.get::<_, Value>(*name) {
+                cache.collect(&t, 0);
+            }
+        }
+
+        /* each modulepath maps the modules adding it to the modulepath they're in */
+        if let Ok(Value::Table(t)) = globals.get::<_, Value>("mpathMapT") {
+            for (mpath, parents) in t.pairs::<String, Table>().flatten() {
+                for (full_name, _) in parents.pairs::<String, Value>().flatten() {
+                    cache
+                        .modulepaths
+                        .entry(full_name)
+                        .or_default()
+                        .push(mpath.clone());
+                }
+            }
+        }
+
+        debug!(
+            "Loaded {} modules from spider cache {}",
+            cache.entries.len(),
+            path.display()
+        );
+
+        Ok(cache)
+    }
+
+    /* collect() walks the cache tables looking for module entries */
+    fn collect(&mut self, t: &Table, depth: usize) {
+        if depth > MAX_CACHE_DEPTH {
+            return;
+        }
+
+        /* module entries name their modulefile with fn (spiderT) or path (moduleT) */
+        let file = ["fn", "path"]
+            .iter()
+            .find_map(|k| match t.get::<_, Value>(*k) {
+                Ok(Value::String(s)) => s.to_str().ok().map(|x| x.to_string()),
+                _ => None,
+            });
+
+        if let (Some(file), Ok(Value::Table(_))) = (&file, t.get::<_, Value>("pathA")) {
+            self.entries.insert(PathBuf::from(file), entry(t));
+            return;
+        }
+
+        for (_, v) in t.clone().pairs::<Value, Value>().flatten() {
+            if let Value::Table(child) = v {
+                self.collect(&child, depth + 1);
+            }
+        }
+    }
+
+    /*
+     * effects() returns the effects of a modulefile as recorded in the cache.
+     * modulefiles changed after the cache was written aren't trusted.
+     */
+
+    pub fn effects(&self, file: &crawl::ModuleFile) -> Option<Effects> {
+        let entry = self.entries.get(&file.path)?;
+        let mtime = fs::metadata(&file.path).and_then(|x| x.modified()).ok()?;
+
+        if mtime > self.mtime {
+            return None;
+        }
+
+        let mut effects = Effects::default();
+
+        if !entry.paths.is_empty() {
+            effects
+                .env
+                .push(EnvOp::Prepend("PATH".to_string(), entry.paths.join(":")));
+        }
+
+        if !entry.lpaths.is_empty() {
+            effects.env.push(EnvOp::Prepend(
+                "LD_LIBRARY_PATH".to_string(),
+                entry.lpaths.join(":"),
+            ));
+        }
+
+        if let Some(dirs) = self.modulepaths.get(&file.code) {
+            effects
+                .env
+                .push(EnvOp::Prepend("MODULEPATH".to_string(), dirs.join(":")));
+        }
+
+        effects.whatis = entry.whatis.clone();
+        effects.help = entry.help.clone();
+
+        Some(effects)
+    }
+}
+
+fn entry(t: &Table) -> Entry {
+    Entry {
+        paths: keys(t, "pathA"),
+        lpaths: keys(t, "lpathA"),
+        whatis: match t.get::<_, Value>("whatis") {
+            Ok(Value::Table(w)) => w.sequence_values::<String>().flatten().collect(),
+            _ => Vec::new(),
+        },
+        help: t.get::<_, String>("help").unwrap_or_default(),
+    }
+}
+
+/* keys() returns the sorted keys of a set-like table field, like pathA */
+fn keys(t: &Table, field: &str) -> Vec<String> {
+    let mut out: Vec<String> = match t.get::<_, Value>(field) {
+        Ok(Value::Table(set)) => set
+            .pairs::<String, Value>()
+            .flatten()
+            .map(|(k, _)| k)
+            .collect(),
+        _ => Vec::new(),
+    };
+
+    out.sort();
+    out
+}
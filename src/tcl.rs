@@ -0,0 +1,1906 @@
+/*
+ * tcl.rs
+ *
+ * minimal tcl evaluator for environment modules modulefiles
+ *
+ * this is nowhere near a complete tcl implementation. it understands the
+ * subset of the language that shows up in real modulefiles (variables,
+ * substitution, conditionals, procs and the modules commands) and quietly
+ * ignores everything else. nothing in here modifies the real environment or
+ * runs external commands; every environment change is recorded instead.
+ */
+
+use crate::analysis::{Effects, EnvOp};
+use crate::crawl;
+
+use regex::RegexBuilder;
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::path::{Path, PathBuf};
+
+/* guards against runaway recursion and loops in broken modulefiles */
+const MAX_DEPTH: usize = 64;
+const MAX_ITERATIONS: usize = 10000;
+
+/* total commands a modulefile may run, however its loops are nested */
+const MAX_COMMANDS: usize = 200_000;
+
+/* largest string a command may build */
+const MAX_STRING_SIZE: usize = 16 * 1024 * 1024;
+
+enum Flow {
+    Break,
+    Continue,
+    Return(String),
+    Error(String),
+}
+
+type TclResult = Result<String, Flow>;
+
+/*
+ * eval() runs a tcl modulefile in load mode and returns the recorded effects
+ * evaluation errors stop the modulefile, but effects recorded so far are kept
+ */
+
+pub fn eval(contents: &str, file: &crawl::ModuleFile) -> Effects {
+    let mut interp = Interp::new(file);
+
+    if let Err(Flow::Error(e)) = interp.eval(contents) {
+        debug!("Stopped evaluating {}: {}", file.path.display(), e);
+    }
+
+    /* the help text is whatever ModulesHelp prints */
+    if let Some((params, body)) = interp.procs.get("ModulesHelp").cloned() {
+        interp.output = Some(String::new());
+
+        if interp.call_proc(&params, &body, &[]).is_ok() {
+            interp.effects.help = interp.output.take().unwrap_or_default();
+        }
+    }
+
+    /* .version files name the default version of their directory */
+    if let Some(version) = interp.frames[0].vars.get("ModulesVersion").cloned() {
+        interp
+            .effects
+            .versions
+            .push((format!("/{}", version), "default".to_string()));
+    }
+
+    interp.effects
+}
+
+#[derive(Default)]
+struct Frame {
+    vars: HashMap<String, String>,
+    globals: HashSet<String>,
+}
+
+struct Interp {
+    frames: Vec<Frame>,
+    env: HashMap<String, String>,
+    procs: HashMap<String, (String, String)>,
+    effects: Effects,
+    code: String,
+    path: PathBuf,
+    depth: usize,
+    budget: usize, /* commands left to run */
+    output: Option<String>,
+}
+
+impl Interp {
+    fn new(file: &crawl::ModuleFile) -> Interp {
+        let mut global = Frame::default();
+
+        global.vars.insert(
+            "ModulesCurrentModulefile".to_string(),
+            file.path.to_string_lossy().to_string(),
+        );
+
+        Interp {
+            frames: vec![global],
+            env: env::vars().collect(),
+            procs: HashMap::new(),
+            effects: Effects::default(),
+            code: file.code.clone(),
+            path: file.path.clone(),
+            depth: 0,
+            budget: MAX_COMMANDS,
+            output: None,
+        }
+    }
+
+    fn eval(&mut self, script: &str) -> TclResult {
+        if self.depth >= MAX_DEPTH {
+            return Err(Flow::Error("too many nested evaluations".to_string()));
+        }
+
+        self.depth += 1;
+        let res = self.eval_script(script);
+        self.depth -= 1;
+
+        res
+    }
+
+    fn eval_script(&mut self, script: &str) -> TclResult {
+        let s: Vec<char> = script.chars().collect();
+        let mut pos = 0;
+        let mut result = String::new();
+
+        while let Some(words) = self.parse_command(&s, &mut pos)? {
+            if !words.is_empty() {
+                result = self.invoke(words)?;
+            }
+        }
+
+        Ok(result)
+    }
+
+    /*
+     * parser: splits a script into commands and performs substitution
+     */
+
+    fn parse_command(&mut self, s: &[char], pos: &mut usize) -> Result<Option<Vec<String>>, Flow> {
+        /* skip separators and comments before the command */
+        loop {
+            while *pos < s.len() && (s[*pos].is_whitespace() || s[*pos] == ';') {
+                *pos += 1;
+            }
+
+            if *pos < s.len() && s[*pos] == '#' {
+                while *pos < s.len() && s[*pos] != '\n' {
+                    if s[*pos] == '\\' {
+                        *pos += 1;
+                    }
+                    *pos += 1;
+                }
+            } else {
+                break;
+            }
+        }
+
+        if *pos >= s.len() {
+            return Ok(None);
+        }
+
+        let mut words = Vec::new();
+
+        loop {
+            while *pos < s.len() {
+                match s[*pos] {
+                    ' ' | '\t' | '\r' => *pos += 1,
+                    '\\' if s.get(*pos + 1) == Some(&'\n') => *pos += 2,
+                    _ => break,
+                }
+            }
+
+            if *pos >= s.len() || s[*pos] == '\n' || s[*pos] == ';' {
+                *pos += 1;
+                return Ok(Some(words));
+            }
+
+            /* {*} argument expansion */
+            if s[*pos..].starts_with(&['{', '*', '}'])
+                && s.get(*pos + 3).map(|c| !c.is_whitespace()).unwrap_or(false)
+            {
+                *pos += 3;
+                let word = self.parse_word(s, pos)?;
+                words.extend(parse_list(&word));
+            } else {
+                words.push(self.parse_word(s, pos)?);
+            }
+        }
+    }
+
+    fn parse_word(&mut self, s: &[char], pos: &mut usize) -> TclResult {
+        let mut out = String::new();
+
+        match s[*pos] {
+            '{' => {
+                let end = match_brace(s, *pos)?;
+                out.extend(&s[*pos + 1..end]);
+                *pos = end + 1;
+            }
+            '"' => {
+                *pos += 1;
+                while *pos < s.len() && s[*pos] != '"' {
+                    self.subst_one(s, pos, &mut out)?;
+                }
+
+                if *pos >= s.len() {
+                    return Err(Flow::Error("missing \"".to_string()));
+                }
+                *pos += 1;
+            }
+            _ => {
+                while *pos < s.len() {
+                    match s[*pos] {
+                        ' ' | '\t' | '\r' | '\n' | ';' => break,
+                        '\\' if s.get(*pos + 1) == Some(&'\n') => break,
+                        _ => self.subst_one(s, pos, &mut out)?,
+                    }
+                }
+            }
+        }
+
+        Ok(out)
+    }
+
+    /* subst_one() consumes a single character, escape, variable or command */
+    fn subst_one(&mut self, s: &[char], pos: &mut usize, out: &mut String) -> Result<(), Flow> {
+        match s[*pos] {
+            '\\' => {
+                *pos += 1;
+
+                match s.get(*pos) {
+                    Some('n') => out.push('\n'),
+                    Some('t') => out.push('\t'),
+                    Some('r') => out.push('\r'),
+                    Some('\n') => {
+                        out.push(' ');
+                        while *pos + 1 < s.len() && (s[*pos + 1] == ' ' || s[*pos + 1] == '\t') {
+                            *pos += 1;
+                        }
+                    }
+                    Some(c) => out.push(*c),
+                    None => out.push('\\'),
+                }
+
+                *pos += 1;
+            }
+            '$' => {
+                *pos += 1;
+
+                let mut name = String::new();
+
+                if s.get(*pos) == Some(&'{') {
+                    while *pos + 1 < s.len() && s[*pos + 1] != '}' {
+                        *pos += 1;
+                        name.push(s[*pos]);
+                    }
+                    *pos += 2;
+                } else {
+                    while *pos < s.len() {
+                        let c = s[*pos];
+
+                        if c.is_alphanumeric() || c == '_' {
+                            name.push(c);
+                            *pos += 1;
+                        } else if c == ':' && s.get(*pos + 1) == Some(&':') {
+                            name.push_str("::");
+                            *pos += 2;
+                        } else {
+                            break;
+                        }
+                    }
+
+                    if name.is_empty() {
+                        out.push('$');
+                        return Ok(());
+                    }
+
+                    if s.get(*pos) == Some(&'(') {
+                        *pos += 1;
+
+                        let mut index = String::new();
+
+                        while *pos < s.len() && s[*pos] != ')' {
+                            self.subst_one(s, pos, &mut index)?;
+                        }
+
+                        *pos += 1;
+                        name = format!("{}({})", name, index);
+                    }
+                }
+
+                out.push_str(&self.get_var(&name)?);
+            }
+            '[' => {
+                let end = match_bracket(s, *pos)?;
+                let script: String = s[*pos + 1..end].iter().collect();
+
+                *pos = end + 1;
+                out.push_str(&self.eval(&script)?);
+            }
+            c => {
+                out.push(c);
+                *pos += 1;
+            }
+        }
+
+        Ok(())
+    }
+
+    /*
+     * variables: arrays are stored flat as "name(index)", env() maps to the
+     * simulated environment
+     */
+
+    fn get_var(&self, name: &str) -> TclResult {
+        let name = name.trim_start_matches("::");
+
+        if let Some(var) = env_index(name) {
+            return self.env.get(var).cloned().ok_or_else(|| {
+                Flow::Error(format!("can't read \"env({})\": no such variable", var))
+            });
+        }
+
+        self.lookup_var(name)
+            .cloned()
+            .ok_or_else(|| Flow::Error(format!("can't read \"{}\": no such variable", name)))
+    }
+
+    fn lookup_var(&self, name: &str) -> Option<&String> {
+        let frame = self.frames.last().unwrap();
+
+        if let Some(v) = frame.vars.get(name) {
+            return Some(v);
+        }
+
+        /* fall back to globals even without a `global` declaration */
+        self.frames[0].vars.get(name)
+    }
+
+    fn set_var(&mut self, name: &str, value: String) {
+        let global = name.starts_with("::");
+        let name = name.trim_start_matches("::");
+
+        if let Some(var) = env_index(name) {
+            self.env.insert(var.to_string(), value);
+            return;
+        }
+
+        let frame = if global || self.frames.last().unwrap().globals.contains(name) {
+            &mut self.frames[0]
+        } else {
+            self.frames.last_mut().unwrap()
+        };
+
+        frame.vars.insert(name.to_string(), value);
+    }
+
+    fn unset_var(&mut self, name: &str) {
+        let name = name.trim_start_matches("::");
+
+        if let Some(var) = env_index(name) {
+            self.env.remove(var);
+            return;
+        }
+
+        self.frames.last_mut().unwrap().vars.remove(name);
+        self.frames[0].vars.remove(name);
+    }
+
+    fn var_exists(&self, name: &str) -> bool {
+        let name = name.trim_start_matches("::");
+
+        match env_index(name) {
+            Some(var) => self.env.contains_key(var),
+            None => self.lookup_var(name).is_some(),
+        }
+    }
+
+    /*
+     * command dispatch
+     */
+
+    fn invoke(&mut self, words: Vec<String>) -> TclResult {
+        let args = &words[1..];
+
+        if self.budget == 0 {
+            return Err(Flow::Error("too many commands evaluated".to_string()));
+        }
+
+        self.budget -= 1;
+
+        match words[0].trim_start_matches("::") {
+            /* modules commands */
+            "setenv" | "pushenv" => {
+                let args = skip_flags(args);
+                let var = arg(args, 0)?;
+                let value = args.get(1).cloned().unwrap_or_default();
+
+                self.env.insert(var.clone(), value.clone());
+                self.effects.env.push(EnvOp::Set(var, value));
+                Ok(String::new())
+            }
+            "unsetenv" => {
+                let var = arg(skip_flags(args), 0)?;
+
+                self.env.remove(&var);
+                self.effects.env.push(EnvOp::Unset(var));
+                Ok(String::new())
+            }
+            "prepend-path" | "append-path" => self.cmd_path(words[0] == "prepend-path", args),
+            "module-info" => self.cmd_module_info(args),
+            "module" => self.cmd_module(args),
+            "prereq" | "depends-on" | "always-load" => {
+                /* prereq with several modules only needs one of them */
+                let names: Vec<String> = args
+                    .iter()
+                    .filter(|x| !x.starts_with('-'))
+                    .cloned()
+                    .collect();
+                let names = if words[0] == "prereq" {
+                    &names[..names.len().min(1)]
+                } else {
+                    &names[..]
+                };
+
+                self.effects.deps.extend(names.iter().cloned());
+                Ok(String::new())
+            }
+            "conflict" => {
+                let names = args.iter().filter(|x| !x.starts_with('-')).cloned();
+
+                self.effects.conflicts.extend(names);
+                Ok(String::new())
+            }
+            "family" => {
+                self.effects.families.extend(args.iter().cloned());
+                Ok(String::new())
+            }
+            "getenv" => {
+                let args = skip_flags(args);
+                let var = arg(args, 0)?;
+
+                Ok(self
+                    .env
+                    .get(&var)
+                    .cloned()
+                    .or_else(|| args.get(1).cloned())
+                    .unwrap_or_default())
+            }
+            "module-version" => {
+                if let Some((module, names)) = args.split_first() {
+                    for name in names {
+                        self.effects.versions.push((module.clone(), name.clone()));
+                    }
+                }
+                Ok(String::new())
+            }
+            "module-alias" => {
+                if args.len() >= 2 {
+                    self.effects
+                        .aliases
+                        .push((args[0].clone(), args[1].clone()));
+                }
+                Ok(String::new())
+            }
+            "module-hide" | "module-forbid" | "hide-version" | "hide-modulefile" => {
+                self.effects.hidden.extend(hide_names(args));
+                Ok(String::new())
+            }
+            "module-whatis" => {
+                self.effects.whatis.push(args.join(" "));
+                Ok(String::new())
+            }
+            "puts" => {
+                /* output is only kept while capturing help text */
+                if let Some(out) = self.output.as_mut() {
+                    let nonewline = args.first().map(|x| x == "-nonewline").unwrap_or(false);
+
+                    if let Some(text) = args.last() {
+                        out.push_str(text);
+                    }
+
+                    if !nonewline {
+                        out.push('\n');
+                    }
+                }
+                Ok(String::new())
+            }
+            "is-loaded" | "is-saved" | "is-used" | "is-avail" => Ok("0".to_string()),
+            "uname" => Ok(match args.first().map(|x| x.as_str()) {
+                Some("sysname") => "Linux".to_string(),
+                Some("machine") => env::consts::ARCH.to_string(),
+                _ => String::new(),
+            }),
+
+            /* core tcl commands */
+            "set" => match args.len() {
+                1 => self.get_var(&args[0]),
+                2 => {
+                    self.set_var(&args[0], args[1].clone());
+                    Ok(args[1].clone())
+                }
+                _ => Err(wrong_args("set varName ?newValue?")),
+            },
+            "unset" => {
+                for name in skip_flags(args) {
+                    self.unset_var(name);
+                }
+                Ok(String::new())
+            }
+            "append" => {
+                let name = arg(args, 0)?;
+                let mut value = self.get_var(&name).unwrap_or_default();
+
+                value.extend(args[1..].iter().map(|x| x.as_str()));
+                self.set_var(&name, value.clone());
+                Ok(value)
+            }
+            "lappend" => {
+                let name = arg(args, 0)?;
+                let mut list = parse_list(&self.get_var(&name).unwrap_or_default());
+
+                list.extend(args[1..].iter().cloned());
+
+                let value = make_list(&list);
+                self.set_var(&name, value.clone());
+                Ok(value)
+            }
+            "incr" => {
+                let name = arg(args, 0)?;
+                let step = args.get(1).and_then(|x| parse_num(x)).unwrap_or(1.0);
+                let value = self
+                    .get_var(&name)
+                    .ok()
+                    .and_then(|x| parse_num(&x))
+                    .unwrap_or(0.0);
+                let value = format_num(value + step);
+
+                self.set_var(&name, value.clone());
+                Ok(value)
+            }
+            "global" => {
+                for name in args {
+                    let name = name.trim_start_matches("::").to_string();
+                    self.frames.last_mut().unwrap().globals.insert(name);
+                }
+                Ok(String::new())
+            }
+            "variable" => {
+                for pair in args.chunks(2) {
+                    let name = pair[0].trim_start_matches("::").to_string();
+
+                    if let Some(value) = pair.get(1) {
+                        self.frames[0].vars.insert(name.clone(), value.clone());
+                    }
+
+                    self.frames.last_mut().unwrap().globals.insert(name);
+                }
+                Ok(String::new())
+            }
+            "if" => self.cmd_if(args),
+            "switch" => self.cmd_switch(args),
+            "foreach" => self.cmd_foreach(args),
+            "while" => {
+                let cond = arg(args, 0)?;
+                let body = arg(args, 1)?;
+
+                for _ in 0..MAX_ITERATIONS {
+                    if !self.expr(&cond)?.truthy()? {
+                        break;
+                    }
+
+                    if !self.loop_body(&body)? {
+                        break;
+                    }
+                }
+                Ok(String::new())
+            }
+            "for" => {
+                let body = arg(args, 3)?;
+
+                self.eval(&args[0])?;
+
+                for _ in 0..MAX_ITERATIONS {
+                    if !self.expr(&args[1])?.truthy()? {
+                        break;
+                    }
+
+                    if !self.loop_body(&body)? {
+                        break;
+                    }
+
+                    self.eval(&args[2])?;
+                }
+                Ok(String::new())
+            }
+            "break" => Err(Flow::Break),
+            "continue" => Err(Flow::Continue),
+            "return" => Err(Flow::Return(
+                skip_flags(args).last().cloned().unwrap_or_default(),
+            )),
+            "error" => Err(Flow::Error(args.first().cloned().unwrap_or_default())),
+            "exit" => Err(Flow::Error("exit called".to_string())),
+            "catch" => {
+                let script = arg(args, 0)?;
+                let (code, value) = match self.eval(&script) {
+                    Ok(v) => (0, v),
+                    Err(Flow::Error(e)) => (1, e),
+                    Err(Flow::Return(v)) => (2, v),
+                    Err(Flow::Break) => (3, String::new()),
+                    Err(Flow::Continue) => (4, String::new()),
+                };
+
+                if let Some(name) = args.get(1) {
+                    self.set_var(name, value);
+                }
+
+                Ok(code.to_string())
+            }
+            "proc" => {
+                let name = arg(args, 0)?.trim_start_matches("::").to_string();
+                let params = arg(args, 1)?;
+                let body = arg(args, 2)?;
+
+                self.procs.insert(name, (params, body));
+                Ok(String::new())
+            }
+            "eval" | "uplevel" => {
+                let args = match args.first() {
+                    Some(x) if words[0] == "uplevel" && parse_num(x).is_some() => &args[1..],
+                    _ => args,
+                };
+
+                self.eval(&args.join(" "))
+            }
+            "expr" => Ok(self.expr(&args.join(" "))?.to_string()),
+            "info" => self.cmd_info(args),
+            "string" => self.cmd_string(args),
+            "file" => self.cmd_file(args),
+            "list" => Ok(make_list(args)),
+            "llength" => Ok(parse_list(&arg(args, 0)?).len().to_string()),
+            "lindex" => {
+                let list = parse_list(&arg(args, 0)?);
+
+                Ok(match args.get(1) {
+                    Some(i) => list_index(&list, i).and_then(|i| list.get(i).cloned()),
+                    None => Some(make_list(&list)),
+                }
+                .unwrap_or_default())
+            }
+            "lrange" => {
+                let list = parse_list(&arg(args, 0)?);
+                let first = list_index(&list, &arg(args, 1)?).unwrap_or(0);
+                let last = list_index(&list, &arg(args, 2)?).unwrap_or(0);
+
+                if first > last || first >= list.len() {
+                    return Ok(String::new());
+                }
+
+                Ok(make_list(&list[first..=last.min(list.len() - 1)]))
+            }
+            "lsearch" => {
+                let list = parse_list(&arg(args, 0)?);
+                let pattern = arg(args, 1)?;
+
+                Ok(list
+                    .iter()
+                    .position(|x| glob_match(&pattern, x))
+                    .map(|i| i as i64)
+                    .unwrap_or(-1)
+                    .to_string())
+            }
+            "concat" => Ok(args
+                .iter()
+                .map(|x| x.trim())
+                .filter(|x| !x.is_empty())
+                .collect::<Vec<&str>>()
+                .join(" ")),
+            "join" => {
+                let sep = args.get(1).map(|x| x.as_str()).unwrap_or(" ");
+                Ok(parse_list(&arg(args, 0)?).join(sep))
+            }
+            "split" => {
+                let value = arg(args, 0)?;
+                let seps = args.get(1).map(|x| x.as_str()).unwrap_or(" \t\n");
+
+                Ok(make_list(
+                    &value
+                        .split(|c| seps.contains(c))
+                        .map(|x| x.to_string())
+                        .collect::<Vec<String>>(),
+                ))
+            }
+            "regexp" => self.cmd_regexp(args),
+            "format" => Ok(format_tcl(&arg(args, 0)?, &args[1..])),
+
+            /* everything else is either a user proc or ignored */
+            name => match self.procs.get(name).cloned() {
+                Some((params, body)) => self.call_proc(&params, &body, args),
+                None => Ok(String::new()),
+            },
+        }
+    }
+
+    fn cmd_path(&mut self, prepend: bool, args: &[String]) -> TclResult {
+        let mut delim = ":".to_string();
+        let mut i = 0;
+
+        /* consume path command options */
+        while i < args.len() && args[i].starts_with('-') {
+            match args[i].as_str() {
+                "-d" | "-delim" | "--delim" => {
+                    delim = arg(args, i + 1)?;
+                    i += 1;
+                }
+                "--index" => i += 1,
+                x if x.starts_with("--delim=") => delim = x["--delim=".len()..].to_string(),
+                _ => (),
+            }
+
+            i += 1;
+        }
+
+        let var = arg(args, i)?;
+
+        for value in &args[i + 1..] {
+            for part in value.split(delim.as_str()).filter(|x| !x.is_empty()) {
+                let current = self.env.get(&var).cloned().unwrap_or_default();
+                let updated = if current.is_empty() {
+                    part.to_string()
+                } else if prepend {
+                    format!("{}{}{}", part, delim, current)
+                } else {
+                    format!("{}{}{}", current, delim, part)
+                };
+
+                self.env.insert(var.clone(), updated);
+                self.effects.env.push(if prepend {
+                    EnvOp::Prepend(var.clone(), part.to_string())
+                } else {
+                    EnvOp::Append(var.clone(), part.to_string())
+                });
+            }
+        }
+
+        Ok(String::new())
+    }
+
+    fn cmd_module(&mut self, args: &[String]) -> TclResult {
+        let names: Vec<String> = args
+            .iter()
+            .skip(1)
+            .filter(|x| !x.starts_with('-'))
+            .cloned()
+            .collect();
+
+        match args.first().map(|x| x.as_str()) {
+            Some("load") | Some("add") | Some("try-load") | Some("try-add") => {
+                self.effects.deps.extend(names)
+            }
+            Some("load-any") => self.effects.deps.extend(names.into_iter().take(1)),
+            _ => (),
+        }
+
+        Ok(String::new())
+    }
+
+    fn cmd_module_info(&mut self, args: &[String]) -> TclResult {
+        let mode = "load";
+
+        Ok(match arg(args, 0)?.as_str() {
+            "mode" => match args.get(1) {
+                Some(m) => bool_str(m == mode),
+                None => mode.to_string(),
+            },
+            "command" => match args.get(1) {
+                Some(m) => bool_str(m == mode),
+                None => mode.to_string(),
+            },
+            "name" | "specified" => self.code.clone(),
+            "shell" => "bash".to_string(),
+            "shelltype" => "sh".to_string(),
+            "flags" => "0".to_string(),
+            _ => String::new(),
+        })
+    }
+
+    fn cmd_if(&mut self, args: &[String]) -> TclResult {
+        let mut i = 0;
+
+        loop {
+            let cond = self.expr(&arg(args, i)?)?.truthy()?;
+            i += 1;
+
+            if args.get(i).map(|x| x == "then").unwrap_or(false) {
+                i += 1;
+            }
+
+            let body = arg(args, i)?;
+            i += 1;
+
+            if cond {
+                return self.eval(&body);
+            }
+
+            match args.get(i).map(|x| x.as_str()) {
+                None => return Ok(String::new()),
+                Some("elseif") => i += 1,
+                Some("else") => return self.eval(&arg(args, i + 1)?),
+                Some(body) => return self.eval(body),
+            }
+        }
+    }
+
+    fn cmd_switch(&mut self, args: &[String]) -> TclResult {
+        let mut glob = false;
+        let mut regexp = false;
+        let mut i = 0;
+
+        while i < args.len() && args[i].starts_with('-') {
+            match args[i].as_str() {
+                "-glob" => glob = true,
+                "-regexp" => regexp = true,
+                "--" => {
+                    i += 1;
+                    break;
+                }
+                _ => (),
+            }
+            i += 1;
+        }
+
+        let value = arg(args, i)?;
+        let cases = if args.len() == i + 2 {
+            parse_list(&args[i + 1])
+        } else {
+            args[i + 1..].to_vec()
+        };
+
+        let mut matched = false;
+
+        for case in cases.chunks(2) {
+            if case.len() < 2 {
+                break;
+            }
+
+            matched = matched
+                || case[0] == "default"
+                || if glob {
+                    glob_match(&case[0], &value)
+                } else if regexp {
+                    RegexBuilder::new(&case[0])
+                        .build()
+                        .map(|r| r.is_match(&value))
+                        .unwrap_or(false)
+                } else {
+                    case[0] == value
+                };
+
+            if matched && case[1] != "-" {
+                return self.eval(&case[1]);
+            }
+        }
+
+        Ok(String::new())
+    }
+
+    fn cmd_foreach(&mut self, args: &[String]) -> TclResult {
+        let names = parse_list(&arg(args, 0)?);
+        let list = parse_list(&arg(args, 1)?);
+        let body = arg(args, 2)?;
+
+        if names.is_empty() {
+            return Ok(String::new());
+        }
+
+        for chunk in list.chunks(names.len()).take(MAX_ITERATIONS) {
+            for (i, name) in names.iter().enumerate() {
+                self.set_var(name, chunk.get(i).cloned().unwrap_or_default());
+            }
+
+            if !self.loop_body(&body)? {
+                break;
+            }
+        }
+
+        Ok(String::new())
+    }
+
+    /* loop_body() evaluates a loop body, returns false if the loop should stop */
+    fn loop_body(&mut self, body: &str) -> Result<bool, Flow> {
+        match self.eval(body) {
+            Ok(_) | Err(Flow::Continue) => Ok(true),
+            Err(Flow::Break) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn cmd_info(&mut self, args: &[String]) -> TclResult {
+        Ok(match arg(args, 0)?.as_str() {
+            "exists" => bool_str(self.var_exists(&arg(args, 1)?)),
+            "script" => self.path.to_string_lossy().to_string(),
+            "level" => (self.frames.len() - 1).to_string(),
+            "procs" | "commands" => make_list(&self.procs.keys().cloned().collect::<Vec<String>>()),
+            "tclversion" => "8.6".to_string(),
+            "patchlevel" => "8.6.0".to_string(),
+            _ => String::new(),
+        })
+    }
+
+    fn cmd_string(&mut self, args: &[String]) -> TclResult {
+        let sub = arg(args, 0)?;
+        let nocase = args.iter().any(|x| x == "-nocase");
+        let args: Vec<&String> = args[1..]
+            .iter()
+            .filter(|x| !x.starts_with('-') || x.len() == 1)
+            .collect();
+        let a = args.first().map(|x| x.to_string()).unwrap_or_default();
+        let b = args.get(1).map(|x| x.to_string()).unwrap_or_default();
+        let (ca, cb) = if nocase {
+            (a.to_lowercase(), b.to_lowercase())
+        } else {
+            (a.clone(), b.clone())
+        };
+
+        Ok(match sub.as_str() {
+            "match" => bool_str(glob_match(&ca, &cb)),
+            "equal" => bool_str(ca == cb),
+            "compare" => (ca.cmp(&cb) as i32).to_string(),
+            "length" => a.chars().count().to_string(),
+            "tolower" => a.to_lowercase(),
+            "toupper" => a.to_uppercase(),
+            "trim" => trim_chars(&a, &b, true, true),
+            "trimleft" => trim_chars(&a, &b, true, false),
+            "trimright" => trim_chars(&a, &b, false, true),
+            "first" => cb
+                .find(&ca)
+                .map(|i| cb[..i].chars().count() as i64)
+                .unwrap_or(-1)
+                .to_string(),
+            "last" => cb
+                .rfind(&ca)
+                .map(|i| cb[..i].chars().count() as i64)
+                .unwrap_or(-1)
+                .to_string(),
+            "index" => {
+                let chars: Vec<char> = a.chars().collect();
+                str_index(chars.len(), &b)
+                    .and_then(|i| chars.get(i))
+                    .map(|c| c.to_string())
+                    .unwrap_or_default()
+            }
+            "range" => {
+                let chars: Vec<char> = a.chars().collect();
+                let first = str_index(chars.len(), &b).unwrap_or(0);
+                let last = args
+                    .get(2)
+                    .and_then(|x| str_index(chars.len(), x))
+                    .unwrap_or(0)
+                    .min(chars.len().saturating_sub(1));
+
+                if first > last || chars.is_empty() {
+                    String::new()
+                } else {
+                    chars[first..=last].iter().collect()
+                }
+            }
+            "map" => {
+                let pairs = parse_list(&a);
+                let mut out = String::new();
+                let mut rest = b.as_str();
+
+                'outer: while !rest.is_empty() {
+                    for pair in pairs.chunks(2) {
+                        if pair.len() == 2
+                            && !pair[0].is_empty()
+                            && rest.starts_with(pair[0].as_str())
+                        {
+                            out.push_str(&pair[1]);
+                            rest = &rest[pair[0].len()..];
+                            continue 'outer;
+                        }
+                    }
+
+                    let c = rest.chars().next().unwrap();
+                    out.push(c);
+                    rest = &rest[c.len_utf8()..];
+                }
+
+                out
+            }
+            "repeat" => {
+                let n = parse_num(&b).unwrap_or(0.0).max(0.0) as usize;
+
+                match a.len().checked_mul(n) {
+                    Some(len) if len <= MAX_STRING_SIZE => a.repeat(n),
+                    _ => return Err(Flow::Error("string repeat result too large".to_string())),
+                }
+            }
+            _ => String::new(),
+        })
+    }
+
+    fn cmd_file(&mut self, args: &[String]) -> TclResult {
+        let sub = arg(args, 0)?;
+        let target = args.get(1).cloned().unwrap_or_default();
+        let path = Path::new(&target);
+
+        Ok(match sub.as_str() {
+            "join" => {
+                let mut out = PathBuf::new();
+
+                for part in &args[1..] {
+                    out.push(part);
+                }
+
+                out.to_string_lossy().to_string()
+            }
+            "dirname" => match path.parent() {
+                Some(p) if !p.as_os_str().is_empty() => p.to_string_lossy().to_string(),
+                _ if target.starts_with('/') => "/".to_string(),
+                _ => ".".to_string(),
+            },
+            "tail" => path
+                .file_name()
+                .map(|x| x.to_string_lossy().to_string())
+                .unwrap_or_default(),
+            "rootname" => match path.extension() {
+                Some(ext) => target[..target.len() - ext.len() - 1].to_string(),
+                None => target.clone(),
+            },
+            "extension" => path
+                .extension()
+                .map(|x| format!(".{}", x.to_string_lossy()))
+                .unwrap_or_default(),
+            "exists" => bool_str(path.exists()),
+            "isdirectory" => bool_str(path.is_dir()),
+            "isfile" => bool_str(path.is_file()),
+            "normalize" => path
+                .canonicalize()
+                .map(|x| x.to_string_lossy().to_string())
+                .unwrap_or(target.clone()),
+            "separator" => "/".to_string(),
+            "split" => make_list(
+                &path
+                    .iter()
+                    .map(|x| x.to_string_lossy().to_string())
+                    .collect::<Vec<String>>(),
+            ),
+            _ => String::new(),
+        })
+    }
+
+    fn cmd_regexp(&mut self, args: &[String]) -> TclResult {
+        let mut nocase = false;
+        let mut i = 0;
+
+        while i < args.len() && args[i].starts_with('-') {
+            match args[i].as_str() {
+                "-nocase" => nocase = true,
+                "--" => {
+                    i += 1;
+                    break;
+                }
+                _ => (),
+            }
+            i += 1;
+        }
+
+        let re = match RegexBuilder::new(&arg(args, i)?)
+            .case_insensitive(nocase)
+            .build()
+        {
+            Ok(re) => re,
+            Err(e) => return Err(Flow::Error(e.to_string())),
+        };
+
+        let value = arg(args, i + 1)?;
+
+        match re.captures(&value) {
+            Some(caps) => {
+                for (n, name) in args[i + 2..].iter().enumerate() {
+                    let group = caps.get(n).map(|x| x.as_str()).unwrap_or("");
+                    self.set_var(name, group.to_string());
+                }
+
+                Ok("1".to_string())
+            }
+            None => Ok("0".to_string()),
+        }
+    }
+
+    fn call_proc(&mut self, params: &str, body: &str, args: &[String]) -> TclResult {
+        let mut frame = Frame::default();
+        let params = parse_list(params);
+
+        for (i, param) in params.iter().enumerate() {
+            let spec = parse_list(param);
+            let name = spec.first().cloned().unwrap_or_default();
+
+            if name == "args" && i == params.len() - 1 {
+                frame
+                    .vars
+                    .insert(name, make_list(args.get(i..).unwrap_or(&[])));
+                break;
+            }
+
+            let value = match (args.get(i), spec.get(1)) {
+                (Some(v), _) => v.clone(),
+                (None, Some(default)) => default.clone(),
+                (None, None) => return Err(wrong_args(&format!("proc with params {}", param))),
+            };
+
+            frame.vars.insert(name, value);
+        }
+
+        self.frames.push(frame);
+        let res = self.eval(body);
+        self.frames.pop();
+
+        match res {
+            Ok(v) | Err(Flow::Return(v)) => Ok(v),
+            Err(Flow::Break) | Err(Flow::Continue) => Ok(String::new()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /*
+     * expr: recursive descent over a small subset of tcl expressions
+     */
+
+    fn expr(&mut self, src: &str) -> Result<Value, Flow> {
+        let mut parser = ExprParser {
+            interp: self,
+            s: src.chars().collect(),
+            pos: 0,
+        };
+
+        let value = parser.ternary()?;
+        parser.skip_ws();
+
+        if parser.pos < parser.s.len() {
+            return Err(Flow::Error(format!(
+                "syntax error in expression \"{}\"",
+                src
+            )));
+        }
+
+        Ok(value)
+    }
+}
+
+#[derive(Clone)]
+enum Value {
+    Num(f64),
+    Str(String),
+}
+
+impl Value {
+    fn num(&self) -> Option<f64> {
+        match self {
+            Value::Num(n) => Some(*n),
+            Value::Str(s) => parse_num(s),
+        }
+    }
+
+    fn truthy(&self) -> Result<bool, Flow> {
+        if let Some(n) = self.num() {
+            return Ok(n != 0.0);
+        }
+
+        match self.to_string().to_lowercase().as_str() {
+            "true" | "yes" | "on" => Ok(true),
+            "false" | "no" | "off" => Ok(false),
+            x => Err(Flow::Error(format!(
+                "expected boolean value but got \"{}\"",
+                x
+            ))),
+        }
+    }
+}
+
+impl std::fmt::Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Value::Num(n) => write!(f, "{}", format_num(*n)),
+            Value::Str(s) => write!(f, "{}", s),
+        }
+    }
+}
+
+struct ExprParser<'a> {
+    interp: &'a mut Interp,
+    s: Vec<char>,
+    pos: usize,
+}
+
+impl<'a> ExprParser<'a> {
+    fn skip_ws(&mut self) {
+        while self
+            .s
+            .get(self.pos)
+            .map(|c| c.is_whitespace())
+            .unwrap_or(false)
+        {
+            self.pos += 1;
+        }
+    }
+
+    /* eat() consumes an operator if present; word operators need a boundary */
+    fn eat(&mut self, op: &str) -> bool {
+        self.skip_ws();
+
+        let chars: Vec<char> = op.chars().collect();
+
+        if !self
+            .s
+            .get(self.pos..)
+            .map(|x| x.starts_with(&chars))
+            .unwrap_or(false)
+        {
+            return false;
+        }
+
+        let next = self.s.get(self.pos + chars.len());
+
+        if chars[0].is_alphabetic() && next.map(|c| c.is_alphanumeric()).unwrap_or(false) {
+            return false;
+        }
+
+        /* don't mistake '=' for '==' or '&' for '&&' */
+        if (op == "<" || op == ">" || op == "!") && next == Some(&'=') {
+            return false;
+        }
+
+        self.pos += chars.len();
+        true
+    }
+
+    fn ternary(&mut self) -> Result<Value, Flow> {
+        let cond = self.or()?;
+
+        if self.eat("?") {
+            let a = self.ternary()?;
+
+            if !self.eat(":") {
+                return Err(Flow::Error("missing ':' in ternary expression".to_string()));
+            }
+
+            let b = self.ternary()?;
+
+            return Ok(if cond.truthy()? { a } else { b });
+        }
+
+        Ok(cond)
+    }
+
+    fn or(&mut self) -> Result<Value, Flow> {
+        let mut lhs = self.and()?;
+
+        while self.eat("||") {
+            let rhs = self.and()?;
+            lhs = Value::Num((lhs.truthy()? || rhs.truthy()?) as i32 as f64);
+        }
+
+        Ok(lhs)
+    }
+
+    fn and(&mut self) -> Result<Value, Flow> {
+        let mut lhs = self.equality()?;
+
+        while self.eat("&&") {
+            let rhs = self.equality()?;
+            lhs = Value::Num((lhs.truthy()? && rhs.truthy()?) as i32 as f64);
+        }
+
+        Ok(lhs)
+    }
+
+    fn equality(&mut self) -> Result<Value, Flow> {
+        let mut lhs = self.relational()?;
+
+        loop {
+            let res = if self.eat("==") {
+                compare(&lhs, &self.relational()?) == std::cmp::Ordering::Equal
+            } else if self.eat("!=") {
+                compare(&lhs, &self.relational()?) != std::cmp::Ordering::Equal
+            } else if self.eat("eq") {
+                lhs.to_string() == self.relational()?.to_string()
+            } else if self.eat("ne") {
+                lhs.to_string() != self.relational()?.to_string()
+            } else if self.eat("in") {
+                parse_list(&self.relational()?.to_string()).contains(&lhs.to_string())
+            } else if self.eat("ni") {
+                !parse_list(&self.relational()?.to_string()).contains(&lhs.to_string())
+            } else {
+                return Ok(lhs);
+            };
+
+            lhs = Value::Num(res as i32 as f64);
+        }
+    }
+
+    fn relational(&mut self) -> Result<Value, Flow> {
+        use std::cmp::Ordering::*;
+
+        let mut lhs = self.additive()?;
+
+        loop {
+            let res = if self.eat("<=") {
+                compare(&lhs, &self.additive()?) != Greater
+            } else if self.eat(">=") {
+                compare(&lhs, &self.additive()?) != Less
+            } else if self.eat("<") {
+                compare(&lhs, &self.additive()?) == Less
+            } else if self.eat(">") {
+                compare(&lhs, &self.additive()?) == Greater
+            } else {
+                return Ok(lhs);
+            };
+
+            lhs = Value::Num(res as i32 as f64);
+        }
+    }
+
+    fn additive(&mut self) -> Result<Value, Flow> {
+        let mut lhs = self.multiplicative()?;
+
+        loop {
+            let add = if self.eat("+") {
+                true
+            } else if self.eat("-") {
+                false
+            } else {
+                return Ok(lhs);
+            };
+
+            let rhs = self.multiplicative()?;
+            let (a, b) = numbers(&lhs, &rhs)?;
+
+            lhs = Value::Num(if add { a + b } else { a - b });
+        }
+    }
+
+    fn multiplicative(&mut self) -> Result<Value, Flow> {
+        let mut lhs = self.unary()?;
+
+        loop {
+            let op = if self.eat("*") {
+                '*'
+            } else if self.eat("/") {
+                '/'
+            } else if self.eat("%") {
+                '%'
+            } else {
+                return Ok(lhs);
+            };
+
+            let rhs = self.unary()?;
+            let (a, b) = numbers(&lhs, &rhs)?;
+
+            if b == 0.0 && op != '*' {
+                return Err(Flow::Error("divide by zero".to_string()));
+            }
+
+            lhs = Value::Num(match op {
+                '*' => a * b,
+                '/' if a.fract() == 0.0 && b.fract() == 0.0 => (a / b).floor(),
+                '/' => a / b,
+                _ => a % b,
+            });
+        }
+    }
+
+    fn unary(&mut self) -> Result<Value, Flow> {
+        if self.eat("!") {
+            let v = self.unary()?;
+            return Ok(Value::Num(!v.truthy()? as i32 as f64));
+        }
+
+        if self.eat("-") {
+            let v = self.unary()?;
+            return match v.num() {
+                Some(n) => Ok(Value::Num(-n)),
+                None => Err(Flow::Error(format!(
+                    "can't use non-numeric string \"{}\"",
+                    v
+                ))),
+            };
+        }
+
+        if self.eat("+") {
+            return self.unary();
+        }
+
+        self.primary()
+    }
+
+    fn primary(&mut self) -> Result<Value, Flow> {
+        self.skip_ws();
+
+        let c = match self.s.get(self.pos) {
+            Some(c) => *c,
+            None => return Err(Flow::Error("premature end of expression".to_string())),
+        };
+
+        match c {
+            '(' => {
+                self.pos += 1;
+                let v = self.ternary()?;
+
+                if !self.eat(")") {
+                    return Err(Flow::Error(
+                        "unbalanced parentheses in expression".to_string(),
+                    ));
+                }
+
+                Ok(v)
+            }
+            '{' => {
+                let end = match_brace(&self.s, self.pos)?;
+                let v = self.s[self.pos + 1..end].iter().collect();
+
+                self.pos = end + 1;
+                Ok(Value::Str(v))
+            }
+            '"' => {
+                let mut out = String::new();
+
+                self.pos += 1;
+                while self.pos < self.s.len() && self.s[self.pos] != '"' {
+                    self.interp.subst_one(&self.s, &mut self.pos, &mut out)?;
+                }
+
+                if self.pos >= self.s.len() {
+                    return Err(Flow::Error("missing \"".to_string()));
+                }
+                self.pos += 1;
+
+                Ok(Value::Str(out))
+            }
+            '$' | '[' => {
+                let mut out = String::new();
+                self.interp.subst_one(&self.s, &mut self.pos, &mut out)?;
+
+                Ok(Value::Str(out))
+            }
+            c if c.is_ascii_digit() || c == '.' => {
+                let start = self.pos;
+
+                while self.pos < self.s.len()
+                    && (self.s[self.pos].is_ascii_alphanumeric() || self.s[self.pos] == '.')
+                {
+                    self.pos += 1;
+                }
+
+                let lit: String = self.s[start..self.pos].iter().collect();
+
+                parse_num(&lit)
+                    .map(Value::Num)
+                    .ok_or_else(|| Flow::Error(format!("invalid number \"{}\"", lit)))
+            }
+            c if c.is_alphabetic() => {
+                let start = self.pos;
+
+                while self.pos < self.s.len() && self.s[self.pos].is_alphanumeric() {
+                    self.pos += 1;
+                }
+
+                let word: String = self.s[start..self.pos].iter().collect();
+
+                /* math functions: only the trivial ones modulefiles might use */
+                if self.eat("(") {
+                    let v = self.ternary()?;
+
+                    if !self.eat(")") {
+                        return Err(Flow::Error(
+                            "unbalanced parentheses in expression".to_string(),
+                        ));
+                    }
+
+                    let n = v.num().unwrap_or(0.0);
+
+                    return Ok(Value::Num(match word.as_str() {
+                        "round" => n.round(),
+                        "int" => n.trunc(),
+                        "abs" => n.abs(),
+                        _ => n,
+                    }));
+                }
+
+                Ok(Value::Str(word))
+            }
+            c => Err(Flow::Error(format!("unexpected \"{}\" in expression", c))),
+        }
+    }
+}
+
+/*
+ * helpers
+ */
+
+fn arg(args: &[String], i: usize) -> TclResult {
+    args.get(i)
+        .cloned()
+        .ok_or_else(|| wrong_args("missing argument"))
+}
+
+fn wrong_args(usage: &str) -> Flow {
+    Flow::Error(format!("wrong # args: {}", usage))
+}
+
+/* skip_flags() drops leading option arguments like --set-if-undef */
+fn skip_flags(args: &[String]) -> &[String] {
+    let n = args.iter().take_while(|x| x.starts_with("--")).count();
+    &args[n..]
+}
+
+/* hide_names() drops the options of module-hide and module-forbid */
+fn hide_names(args: &[String]) -> Vec<String> {
+    let mut out = Vec::new();
+    let mut iter = args.iter();
+
+    while let Some(a) = iter.next() {
+        match a.as_str() {
+            "--not-user" | "--not-group" | "--before" | "--after" | "--message"
+            | "--nearly-message" => {
+                iter.next();
+            }
+            _ if a.starts_with('-') => (),
+            _ => out.push(a.clone()),
+        }
+    }
+
+    out
+}
+
+fn bool_str(b: bool) -> String {
+    (b as i32).to_string()
+}
+
+fn env_index(name: &str) -> Option<&str> {
+    if name.starts_with("env(") && name.ends_with(')') {
+        Some(&name[4..name.len() - 1])
+    } else {
+        None
+    }
+}
+
+fn match_brace(s: &[char], start: usize) -> Result<usize, Flow> {
+    let mut depth = 0;
+    let mut i = start;
+
+    while i < s.len() {
+        match s[i] {
+            '\\' => i += 1,
+            '{' => depth += 1,
+            '}' => {
+                depth -= 1;
+
+                if depth == 0 {
+                    return Ok(i);
+                }
+            }
+            _ => (),
+        }
+        i += 1;
+    }
+
+    Err(Flow::Error("missing close-brace".to_string()))
+}
+
+fn match_bracket(s: &[char], start: usize) -> Result<usize, Flow> {
+    let mut depth = 0;
+    let mut i = start;
+
+    while i < s.len() {
+        match s[i] {
+            '\\' => i += 1,
+            '{' => i = match_brace(s, i)?,
+            '[' => depth += 1,
+            ']' => {
+                depth -= 1;
+
+                if depth == 0 {
+                    return Ok(i);
+                }
+            }
+            _ => (),
+        }
+        i += 1;
+    }
+
+    Err(Flow::Error("missing close-bracket".to_string()))
+}
+
+fn parse_list(s: &str) -> Vec<String> {
+    let chars: Vec<char> = s.chars().collect();
+    let mut out = Vec::new();
+    let mut i = 0;
+
+    loop {
+        while i < chars.len() && chars[i].is_whitespace() {
+            i += 1;
+        }
+
+        if i >= chars.len() {
+            return out;
+        }
+
+        let mut item = String::new();
+
+        match chars[i] {
+            '{' => {
+                let end = match_brace(&chars, i).unwrap_or(chars.len());
+                item.extend(&chars[i + 1..end]);
+                i = end + 1;
+            }
+            '"' => {
+                i += 1;
+                while i < chars.len() && chars[i] != '"' {
+                    if chars[i] == '\\' {
+                        i += 1;
+                    }
+                    if let Some(c) = chars.get(i) {
+                        item.push(*c);
+                    }
+                    i += 1;
+                }
+                i += 1;
+            }
+            _ => {
+                while i < chars.len() && !chars[i].is_whitespace() {
+                    if chars[i] == '\\' {
+                        i += 1;
+                    }
+                    if let Some(c) = chars.get(i) {
+                        item.push(*c);
+                    }
+                    i += 1;
+                }
+            }
+        }
+
+        out.push(item);
+    }
+}
+
+fn make_list(items: &[String]) -> String {
+    items
+        .iter()
+        .map(|x| {
+            if x.is_empty() || x.contains(|c: char| c.is_whitespace() || "{}[]$\"\\;".contains(c)) {
+                format!("{{{}}}", x)
+            } else {
+                x.clone()
+            }
+        })
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+fn list_index(list: &[String], index: &str) -> Option<usize> {
+    str_index(list.len(), index)
+}
+
+/* str_index() resolves tcl indices like 3, end and end-1 */
+fn str_index(len: usize, index: &str) -> Option<usize> {
+    if index == "end" {
+        return len.checked_sub(1);
+    }
+
+    if let Some(off) = index.strip_prefix("end-") {
+        return len.checked_sub(1 + off.parse::<usize>().ok()?);
+    }
+
+    index.parse::<usize>().ok()
+}
+
+fn trim_chars(s: &str, chars: &str, left: bool, right: bool) -> String {
+    let pred = |c: char| {
+        if chars.is_empty() {
+            c.is_whitespace()
+        } else {
+            chars.contains(c)
+        }
+    };
+
+    match (left, right) {
+        (true, true) => s.trim_matches(pred),
+        (true, false) => s.trim_start_matches(pred),
+        _ => s.trim_end_matches(pred),
+    }
+    .to_string()
+}
+
+fn parse_num(s: &str) -> Option<f64> {
+    let s = s.trim();
+
+    if let Some(hex) = s.strip_prefix("0x") {
+        return i64::from_str_radix(hex, 16).ok().map(|x| x as f64);
+    }
+
+    /* rust accepts "inf" and "nan", tcl modulefiles never mean those */
+    if s.is_empty() || s.contains(|c: char| c.is_alphabetic() && c != 'e' && c != 'E') {
+        return None;
+    }
+
+    s.parse::<f64>().ok()
+}
+
+fn format_num(n: f64) -> String {
+    if n.fract() == 0.0 && n.abs() < 1e15 {
+        format!("{}", n as i64)
+    } else {
+        format!("{}", n)
+    }
+}
+
+fn numbers(a: &Value, b: &Value) -> Result<(f64, f64), Flow> {
+    match (a.num(), b.num()) {
+        (Some(a), Some(b)) => Ok((a, b)),
+        _ => Err(Flow::Error(format!(
+            "can't use non-numeric string in \"{} {}\"",
+            a, b
+        ))),
+    }
+}
+
+fn compare(a: &Value, b: &Value) -> std::cmp::Ordering {
+    match (a.num(), b.num()) {
+        (Some(x), Some(y)) => x.partial_cmp(&y).unwrap_or(std::cmp::Ordering::Equal),
+        _ => a.to_string().cmp(&b.to_string()),
+    }
+}
+
+/* format_tcl() handles the plain %s/%d conversions, which is all modulefiles use */
+fn format_tcl(fmt: &str, args: &[String]) -> String {
+    let mut out = String::new();
+    let mut args = args.iter();
+    let mut chars = fmt.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            out.push(c);
+            continue;
+        }
+
+        /* skip flags and width */
+        while let Some(f) = chars.peek() {
+            if f.is_ascii_digit() || "-+ #.".contains(*f) {
+                chars.next();
+            } else {
+                break;
+            }
+        }
+
+        match chars.next() {
+            Some('%') => out.push('%'),
+            Some('d') | Some('i') => out.push_str(&format_num(
+                args.next()
+                    .and_then(|x| parse_num(x))
+                    .unwrap_or(0.0)
+                    .trunc(),
+            )),
+            Some(_) => out.push_str(args.next().map(|x| x.as_str()).unwrap_or("")),
+            None => (),
+        }
+    }
+
+    out
+}
+
+/* glob_match() implements tcl `string match` patterns */
+pub fn glob_match(pattern: &str, s: &str) -> bool {
+    let p: Vec<char> = pattern.chars().collect();
+    let s: Vec<char> = s.chars().collect();
+
+    glob_match_at(&p, &s)
+}
+
+fn glob_match_at(p: &[char], s: &[char]) -> bool {
+    match p.first() {
+        None => s.is_empty(),
+        Some('*') => (0..=s.len()).any(|i| glob_match_at(&p[1..], &s[i..])),
+        Some('?') => !s.is_empty() && glob_match_at(&p[1..], &s[1..]),
+        Some('[') => {
+            let end = match p.iter().position(|c| *c == ']') {
+                Some(e) => e,
+                None => return false,
+            };
+
+            let set = &p[1..end];
+            let c = match s.first() {
+                Some(c) => *c,
+                None => return false,
+            };
+
+            let mut matched = false;
+            let mut i = 0;
+
+            while i < set.len() {
+                if i + 2 < set.len() && set[i + 1] == '-' {
+                    matched |= set[i] <= c && c <= set[i + 2];
+                    i += 3;
+                } else {
+                    matched |= set[i] == c;
+                    i += 1;
+                }
+            }
+
+            matched && glob_match_at(&p[end + 1..], &s[1..])
+        }
+        Some('\\') if p.len() > 1 => s.first() == Some(&p[1]) && glob_match_at(&p[2..], &s[1..]),
+        Some(c) => s.first() == Some(c) && glob_match_at(&p[1..], &s[1..]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crawl::ModuleType;
+
+    fn run(contents: &str) -> Effects {
+        let file = crawl::ModuleFile {
+            path: PathBuf::from("/mods/foo/1.0"),
+            code: "foo/1.0".to_string(),
+            modtype: ModuleType::TCL,
+            hash: None,
+            chain: Vec::new(),
+            default: false,
+            hidden: false,
+            aliases: Vec::new(),
+            activate: None,
+        };
+
+        eval(contents, &file)
+    }
+
+    #[test]
+    fn prepend_path() {
+        let effects =
+            run("#%Module\nprepend-path PATH /opt/foo/bin\nprepend-path PATH /opt/foo/sbin");
+
+        assert_eq!(effects.paths("PATH"), vec!["/opt/foo/bin", "/opt/foo/sbin"]);
+    }
+
+    #[test]
+    fn set_and_env() {
+        let effects = run(
+            "#%Module\nset root /opt/foo\nsetenv FOO_HOME $root\nprepend-path PATH $env(FOO_HOME)/bin",
+        );
+
+        assert_eq!(effects.paths("FOO_HOME"), vec!["/opt/foo"]);
+        assert_eq!(effects.paths("PATH"), vec!["/opt/foo/bin"]);
+    }
+
+    #[test]
+    fn file_join() {
+        let effects = run("#%Module\nprepend-path PATH [file join /opt foo 1.0 bin]");
+
+        assert_eq!(effects.paths("PATH"), vec!["/opt/foo/1.0/bin"]);
+    }
+
+    #[test]
+    fn if_expr() {
+        let effects = run(
+            "#%Module\nset v 3\nif {$v > 2 && \"a\" == \"a\"} {\n  prepend-path PATH /yes/bin\n} else {\n  prepend-path PATH /no/bin\n}\nif {[expr {$v * 2}] == 5} { prepend-path PATH /never/bin }",
+        );
+
+        assert_eq!(effects.paths("PATH"), vec!["/yes/bin"]);
+    }
+
+    #[test]
+    fn unterminated_quote() {
+        let effects =
+            run("#%Module\nprepend-path PATH /before/bin\nif {\"abc} { prepend-path PATH /x/bin }");
+
+        assert_eq!(effects.paths("PATH"), vec!["/before/bin"]);
+
+        let effects = run("#%Module\nprepend-path PATH \"/x/bin");
+
+        assert!(effects.paths("PATH").is_empty());
+    }
+
+    #[test]
+    fn string_repeat_limit() {
+        let effects = run(
+            "#%Module\nset a [string repeat ab 3]\nprepend-path PATH /$a/bin\nset b [string repeat x 99999999999999999999]\nprepend-path PATH /never/bin",
+        );
+
+        assert_eq!(effects.paths("PATH"), vec!["/ababab/bin"]);
+    }
+
+    #[test]
+    fn command_budget() {
+        let effects = run(
+            "#%Module\nprepend-path PATH /before/bin\nwhile 1 { while 1 { while 1 { catch { set a 1 } } } }\nprepend-path PATH /never/bin",
+        );
+
+        assert_eq!(effects.paths("PATH"), vec!["/before/bin"]);
+    }
+}